@@ -1,7 +1,7 @@
 use clap::{AppSettings, Clap};
 use std::path::PathBuf;
 
-use crate::commands::*;
+use crate::{commands::*, output::OutputFormat};
 
 #[derive(Clap, Clone)]
 #[clap(version, author, about, global_setting = AppSettings::ColoredHelp, setting = AppSettings::GlobalVersion, setting = AppSettings::VersionlessSubcommands)]
@@ -19,8 +19,8 @@ pub struct Args {
     #[clap(long)]
     pub arch: Option<String>,
     /// Operate in a mounted guest system (root-only)
-    #[clap(long)]
-    pub sysroot: bool,
+    #[clap(long, parse(from_os_str))]
+    pub sysroot: Option<PathBuf>,
     /// Set a custom package cache location
     #[clap(long, parse(from_os_str))]
     pub cachedir: Option<PathBuf>,
@@ -30,6 +30,12 @@ pub struct Args {
     /// Set a custom configuration file
     #[clap(long, parse(from_os_str))]
     pub config: Option<PathBuf>,
+    /// Read the configuration file as a pacman.conf
+    #[clap(long)]
+    pub pacman_conf: bool,
+    /// Print the effective configuration and where each value came from
+    #[clap(long)]
+    pub debug_config: bool,
     /// Set a custom home directory for GnuPG
     #[clap(long, parse(from_os_str))]
     pub gpgdir: Option<PathBuf>,
@@ -37,14 +43,22 @@ pub struct Args {
     #[clap(long, parse(from_os_str))]
     pub logfile: Option<PathBuf>,
     /// Do not ask for confirmation
-    #[clap(long)]
+    #[clap(long, overrides_with = "confirm")]
     pub noconfirm: bool,
     /// Always ask for confirmation
-    #[clap(long)]
+    #[clap(long, overrides_with = "noconfirm")]
     pub confirm: bool,
     /// Use relaxed timeouts for download
     #[clap(long)]
     pub disable_download_timeout: bool,
+    /// Print query results as text, a JSON array or one JSON object per line
+    #[clap(
+        long,
+        global = true,
+        possible_values = &["text", "json", "ndjson"],
+        default_value = "text"
+    )]
+    pub output: OutputFormat,
     /// The operation to do
     #[clap(subcommand)]
     pub command: Command,
@@ -0,0 +1,180 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::{self, ErrorKind},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use users::{os::unix::UserExt, User};
+
+use crate::{config::AurConfig, utils::confirm};
+
+/// The last revision of a package base the user accepted
+const REVIEWED_REF: &str = "refs/rpac/reviewed";
+
+/// Clones, reviews and builds AUR package bases as the invoking non-root user
+pub struct Builder {
+    user: User,
+    build_dir: PathBuf,
+    base_url: String,
+}
+
+impl Builder {
+    pub fn new(config: &AurConfig) -> io::Result<Self> {
+        let user = build_user()?;
+        let build_dir = match config.build_dir.strip_prefix("~") {
+            Ok(rest) => user.home_dir().join(rest),
+            Err(_) => config.build_dir.clone(),
+        };
+        let builder = Self {
+            user,
+            build_dir,
+            base_url: config.base_url().to_string(),
+        };
+        run(builder
+            .command("mkdir")
+            .arg("-p")
+            .arg(&builder.build_dir))?;
+        Ok(builder)
+    }
+
+    pub fn dir(&self, pkgbase: &str) -> PathBuf {
+        self.build_dir.join(pkgbase)
+    }
+
+    /// Clone or fetch `pkgbase` and show what changed since the last review.
+    /// Returns whether the user accepted the changes.
+    pub fn review(&self, pkgbase: &str, no_confirm: bool) -> io::Result<bool> {
+        let dir = self.dir(pkgbase);
+        if dir.join(".git").exists() {
+            run(self.git(&dir).args(&["fetch", "--quiet", "origin"]))?;
+        } else {
+            run(self
+                .command("git")
+                .args(&["clone", "--quiet"])
+                .arg(format!("{}/{}.git", self.base_url, pkgbase))
+                .arg(&dir))?;
+        }
+
+        // Only moved once the user accepts, a declined clone is shown again
+        let reviewed = output(self.git(&dir).args(&[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            REVIEWED_REF,
+        ]))
+        .ok();
+        let upstream = output(self.git(&dir).args(&["rev-parse", "@{upstream}"]))?;
+        match reviewed {
+            Some(reviewed) if reviewed == upstream => {
+                println!("{}: no changes since last review", pkgbase);
+                return Ok(true);
+            }
+            Some(reviewed) => run(self.git(&dir).args(&[
+                "--no-pager",
+                "diff",
+                "--stat",
+                "--patch",
+                reviewed.as_str(),
+                "@{upstream}",
+            ]))?,
+            None => run(self
+                .git(&dir)
+                .args(&["--no-pager", "show", "@{upstream}:PKGBUILD"]))?,
+        }
+
+        let accepted = confirm(&format!("Proceed with building {}?", pkgbase), no_confirm);
+        if accepted {
+            run(self
+                .git(&dir)
+                .args(&["reset", "--quiet", "--hard", "@{upstream}"]))?;
+            run(self
+                .git(&dir)
+                .args(&["update-ref", REVIEWED_REF, upstream.as_str()]))?;
+        }
+        Ok(accepted)
+    }
+
+    /// Run makepkg for `pkgbase` and return the produced package files
+    pub fn build(&self, pkgbase: &str) -> io::Result<Vec<PathBuf>> {
+        let dir = self.dir(pkgbase);
+        run(self
+            .command("makepkg")
+            .current_dir(&dir)
+            .args(&["--force", "--cleanbuild", "--noconfirm"]))?;
+        let packages = output(self.command("makepkg").current_dir(&dir).arg("--packagelist"))?;
+        Ok(packages
+            .lines()
+            .map(PathBuf::from)
+            .filter(|path| path.exists())
+            .collect())
+    }
+
+    pub fn srcinfo(&self, pkgbase: &str) -> io::Result<String> {
+        fs::read_to_string(self.dir(pkgbase).join(".SRCINFO"))
+    }
+
+    fn git(&self, dir: &Path) -> Command {
+        let mut command = self.command("git");
+        command.arg("-C").arg(dir);
+        command
+    }
+
+    fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        let mut command = Command::new(program);
+        command
+            .uid(self.user.uid())
+            .gid(self.user.primary_group_id())
+            .env("HOME", self.user.home_dir())
+            .env("USER", self.user.name());
+        command
+    }
+}
+
+/// The package name of a built package file (`name-pkgver-pkgrel-arch.pkg.tar.*`)
+pub fn package_name(path: &Path) -> Option<&str> {
+    path.file_name()?.to_str()?.rsplitn(4, '-').nth(3)
+}
+
+fn build_user() -> io::Result<User> {
+    let user = match env::var("SUDO_USER") {
+        Ok(name) => users::get_user_by_name(&name),
+        Err(_) => users::get_user_by_uid(users::get_current_uid()),
+    }
+    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "could not determine the invoking user"))?;
+
+    if user.uid() == 0 {
+        Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "refusing to run makepkg as root",
+        ))
+    } else {
+        Ok(user)
+    }
+}
+
+fn run(command: &mut Command) -> io::Result<()> {
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::Other,
+            format!("{:?} failed with {}", command, status),
+        ))
+    }
+}
+
+fn output(command: &mut Command) -> io::Result<String> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::Other,
+            format!("{:?} failed with {}", command, output.status),
+        ))
+    }
+}
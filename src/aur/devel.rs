@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const DEVEL_SUFFIXES: &[&str] = &["-git", "-hg", "-svn"];
+
+/// Whether `name` follows the naming convention of VCS packages
+pub fn is_devel(name: &str) -> bool {
+    DEVEL_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// A VCS source of a package base and the upstream revision it was last built from
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct VcsSource {
+    pub protocol: String,
+    pub url: String,
+    pub branch: Option<String>,
+    pub revision: String,
+}
+
+impl VcsSource {
+    /// Parse a `source = ` value of a .SRCINFO into the directory makepkg checks it out
+    /// to, protocol, url and branch, ignoring non VCS and pinned sources
+    fn parse(source: &str) -> Option<(String, String, String, Option<String>)> {
+        let mut split = source.splitn(2, "::");
+        let (name, source) = match (split.next(), split.next()) {
+            (Some(name), Some(source)) => (Some(name), source),
+            (source, _) => (None, source?),
+        };
+        let (source, fragment) = match source.find('#') {
+            Some(index) => (&source[..index], Some(&source[index + 1..])),
+            None => (source, None),
+        };
+        let source = source.split('?').next()?;
+
+        let protocol = ["git", "hg", "svn"].iter().find(|protocol| {
+            source.starts_with(&format!("{}+", protocol))
+                || source.starts_with(&format!("{}://", protocol))
+        })?;
+        let url = source.trim_start_matches(&format!("{}+", protocol));
+        // Like makepkg's get_filename
+        let name = name.map_or_else(
+            || {
+                let base = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+                base.trim_end_matches(".git").to_string()
+            },
+            String::from,
+        );
+
+        let mut branch = None;
+        if let Some(fragment) = fragment {
+            let mut split = fragment.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some("branch"), Some(value)) => branch = Some(value.to_string()),
+                // Pinned to a fixed revision, can never be outdated
+                (Some(_), Some(_)) => return None,
+                _ => {}
+            }
+        }
+
+        Some((name, protocol.to_string(), url.to_string(), branch))
+    }
+
+    /// The revision makepkg checked out to `dir` and built
+    fn local_revision(protocol: &str, dir: &Path) -> io::Result<String> {
+        let mut command = Command::new(protocol);
+        match protocol {
+            "git" => command.arg("-C").arg(dir).args(&["rev-parse", "HEAD"]),
+            "hg" => command.arg("--cwd").arg(dir).args(&["identify", "--id"]),
+            _ => command.args(&["info", "--show-item", "revision"]).arg(dir),
+        };
+
+        let output = command.output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.split_whitespace().next() {
+            Some(revision) if output.status.success() => Ok(revision.to_string()),
+            _ => Err(io::Error::new(
+                ErrorKind::Other,
+                format!("could not get the built revision of {}", dir.display()),
+            )),
+        }
+    }
+
+    /// Ask upstream for the current revision of this source
+    fn remote_revision(protocol: &str, url: &str, branch: Option<&str>) -> io::Result<String> {
+        let mut command = Command::new(protocol);
+        match protocol {
+            "git" => {
+                command.args(&["ls-remote", url]).arg(
+                    branch.map_or_else(|| "HEAD".to_string(), |b| format!("refs/heads/{}", b)),
+                );
+            }
+            "hg" => {
+                command.args(&["identify", url]);
+                if let Some(branch) = branch {
+                    command.args(&["--rev", branch]);
+                }
+            }
+            _ => {
+                command.args(&["info", "--show-item", "revision", url]);
+            }
+        }
+
+        let output = command.output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.split_whitespace().next() {
+            Some(revision) if output.status.success() => Ok(revision.to_string()),
+            _ => Err(io::Error::new(
+                ErrorKind::Other,
+                format!("could not get the upstream revision of {}", url),
+            )),
+        }
+    }
+
+    /// The revisions of all VCS sources in a .SRCINFO as checked out in `srcdir`,
+    /// so upstream changes made during the build are still detected later
+    pub fn from_srcinfo(srcinfo: &str, srcdir: &Path) -> io::Result<Vec<Self>> {
+        let mut sources = Vec::new();
+        for line in srcinfo.lines() {
+            let mut split = line.trim().splitn(2, '=');
+            let (key, value) = match (split.next(), split.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => continue,
+            };
+            if key != "source" && !key.starts_with("source_") {
+                continue;
+            }
+            if let Some((name, protocol, url, branch)) = Self::parse(value) {
+                let revision = Self::local_revision(&protocol, &srcdir.join(name))?;
+                sources.push(Self {
+                    protocol,
+                    url,
+                    branch,
+                    revision,
+                });
+            }
+        }
+        Ok(sources)
+    }
+
+    fn is_outdated(&self) -> bool {
+        Self::remote_revision(&self.protocol, &self.url, self.branch.as_deref())
+            .map_or(false, |revision| revision != self.revision)
+    }
+}
+
+/// The upstream revisions devel packages were built from, kept under the database path
+#[derive(Serialize, Deserialize, Default)]
+pub struct DevelState {
+    #[serde(default)]
+    pub packages: HashMap<String, Vec<VcsSource>>,
+}
+
+impl DevelState {
+    pub fn path(dbpath: &Path) -> PathBuf {
+        dbpath.join("rpac").join("devel.toml")
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => toml::from_str(&data)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err)),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data =
+            toml::to_string(self).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        fs::write(path, data)
+    }
+
+    pub fn record(&mut self, pkgbase: &str, sources: Vec<VcsSource>) {
+        self.packages.insert(pkgbase.to_string(), sources);
+    }
+
+    /// Package bases whose upstream moved on since they were built
+    pub fn outdated(&self) -> Vec<&str> {
+        self.packages
+            .iter()
+            .filter(|(_, sources)| sources.iter().any(VcsSource::is_outdated))
+            .map(|(pkgbase, _)| pkgbase.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(&["-c", "user.name=rpac", "-c", "user.email=rpac@localhost"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// A bare upstream repository with one commit and a clone to commit from
+    fn upstream() -> (TempDir, PathBuf, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let upstream = root.path().join("project.git");
+        let work = root.path().join("work");
+        fs::create_dir(&upstream).unwrap();
+        fs::create_dir(&work).unwrap();
+        git(&upstream, &["init", "--quiet", "--bare"]);
+        git(&upstream, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+        git(&work, &["init", "--quiet"]);
+        git(&work, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+        commit(&work, &upstream, "first");
+        (root, upstream, work)
+    }
+
+    fn commit(work: &Path, upstream: &Path, message: &str) {
+        git(work, &["commit", "--quiet", "--allow-empty", "-m", message]);
+        git(
+            work,
+            &["push", "--quiet", upstream.to_str().unwrap(), "main"],
+        );
+    }
+
+    #[test]
+    fn parse_sources() {
+        assert_eq!(
+            VcsSource::parse("git+https://example.com/project.git"),
+            Some((
+                "project".to_string(),
+                "git".to_string(),
+                "https://example.com/project.git".to_string(),
+                None
+            ))
+        );
+        assert_eq!(
+            VcsSource::parse("dir::git://example.com/project#branch=dev"),
+            Some((
+                "dir".to_string(),
+                "git".to_string(),
+                "git://example.com/project".to_string(),
+                Some("dev".to_string())
+            ))
+        );
+        assert_eq!(VcsSource::parse("git+https://example.com/p.git#tag=v1"), None);
+        assert_eq!(VcsSource::parse("https://example.com/p.tar.gz"), None);
+    }
+
+    #[test]
+    fn records_the_built_revision() {
+        let (root, upstream, work) = upstream();
+        let srcdir = root.path().join("src");
+        fs::create_dir(&srcdir).unwrap();
+        git(
+            &srcdir,
+            &["clone", "--quiet", upstream.to_str().unwrap(), "project"],
+        );
+        let built = git(&srcdir.join("project"), &["rev-parse", "HEAD"]);
+        // Upstream moves on while the package is being built
+        commit(&work, &upstream, "second");
+
+        let srcinfo = format!(
+            "pkgbase = project-git\n\tsource = git+file://{}\n",
+            upstream.display()
+        );
+        let sources = VcsSource::from_srcinfo(&srcinfo, &srcdir).unwrap();
+
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].revision, built);
+        assert!(sources[0].is_outdated());
+    }
+
+    #[test]
+    fn current_builds_are_not_outdated() {
+        let (root, upstream, work) = upstream();
+        let srcdir = root.path().join("src");
+        fs::create_dir(&srcdir).unwrap();
+        git(
+            &srcdir,
+            &["clone", "--quiet", upstream.to_str().unwrap(), "project"],
+        );
+        let srcinfo = format!(
+            "pkgbase = project-git\n\tsource = git+file://{}\n",
+            upstream.display()
+        );
+        let mut state = DevelState::default();
+        state.record(
+            "project-git",
+            VcsSource::from_srcinfo(&srcinfo, &srcdir).unwrap(),
+        );
+        assert!(state.outdated().is_empty());
+
+        commit(&work, &upstream, "second");
+        assert_eq!(state.outdated(), ["project-git"]);
+    }
+}
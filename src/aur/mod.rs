@@ -0,0 +1,228 @@
+mod build;
+mod devel;
+mod resolve;
+
+pub use build::{package_name, Builder};
+pub use devel::{is_devel, DevelState, VcsSource};
+pub use resolve::{BuildPlan, ResolveError, Resolver};
+
+use alpm::{vercmp, Alpm};
+use raur::{Handle, Package, Raur, SearchBy};
+use std::{cmp::Ordering, future::Future};
+
+use crate::{
+    config::AurConfig,
+    error::{self, Error},
+    utils::Join,
+};
+
+pub const REPO_NAME: &str = "aur";
+
+pub fn handle(config: &AurConfig) -> Handle {
+    Handle::new_with_url(config.url.as_str())
+}
+
+/// Run a RPC future to completion on a single threaded runtime
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+pub fn search_by(strategy: &str) -> SearchBy {
+    match strategy {
+        "name" => SearchBy::Name,
+        "maintainer" => SearchBy::Maintainer,
+        _ => SearchBy::NameDesc,
+    }
+}
+
+pub fn search<R: Raur>(raur: &R, query: &str, strategy: SearchBy) -> Result<Vec<Package>, R::Err> {
+    let mut packages = block_on(raur.search_by(query, strategy))?;
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packages)
+}
+
+pub fn info<R: Raur, S: AsRef<str> + Send + Sync>(
+    raur: &R,
+    names: &[S],
+) -> Result<Vec<Package>, R::Err> {
+    block_on(raur.info(names))
+}
+
+/// AUR packages that are newer than the installed foreign packages, including
+/// ones in `IgnorePkg`/`IgnoreGroup` so callers can report them
+pub fn updates<R: Raur>(raur: &R, handle: &Alpm) -> error::Result<Vec<Package>>
+where
+    Error: From<R::Err>,
+{
+    let localdb = handle.localdb();
+    let foreign: Vec<&str> = localdb
+        .pkgs()?
+        .filter(|package| handle.syncdbs().all(|db| db.pkg(package.name()).is_err()))
+        .map(|package| package.name())
+        .collect();
+    if foreign.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(info(raur, &foreign)?
+        .into_iter()
+        .filter(|aur| {
+            localdb.pkg(aur.name.as_str()).map_or(false, |local| {
+                vercmp(local.version().to_string(), aur.version.clone()) == Ordering::Less
+            })
+        })
+        .collect())
+}
+
+pub fn print_search(package: &Package, handle: &Alpm, quiet: bool) {
+    if quiet {
+        println!("{}", package.name);
+        return;
+    }
+
+    print!(
+        "{}/{} {} (+{} {:.2}%)",
+        REPO_NAME, package.name, package.version, package.num_votes, package.popularity
+    );
+    if package.out_of_date.is_some() {
+        print!(" [out-of-date]");
+    }
+    if handle.localdb().pkg(package.name.as_str()).is_ok() {
+        print!(" [installed]");
+    }
+    println!();
+    println!(
+        "    {}",
+        package.description.as_ref().map_or("None", |desc| desc)
+    );
+}
+
+pub fn print_info(package: &Package, config: &AurConfig) {
+    println!("Repository : {}", REPO_NAME);
+    println!("Name : {}", package.name);
+    println!("Version : {}", package.version);
+    println!(
+        "Description : {}",
+        package.description.as_ref().map_or("None", |desc| desc)
+    );
+    println!("URL : {}", package.url.as_ref().map_or("None", |url| url));
+    println!(
+        "AUR URL : {}/packages/{}",
+        config.base_url(),
+        package.name
+    );
+    println!("Licenses : {}", package.license.iter().join(" "));
+    println!("Groups : {}", package.groups.iter().join(" "));
+    println!("Provides : {}", package.provides.iter().join(" "));
+    println!("Depends On : {}", package.depends.iter().join(" "));
+    println!("Make Deps : {}", package.make_depends.iter().join(" "));
+    println!("Check Deps : {}", package.check_depends.iter().join(" "));
+    println!("Optional Deps : {}", package.opt_depends.iter().join("\n"));
+    println!("Conflicts With : {}", package.conflicts.iter().join(" "));
+    println!("Replaces : {}", package.replaces.iter().join(" "));
+    println!(
+        "Maintainer : {}",
+        package.maintainer.as_ref().map_or("None", |m| m)
+    );
+    println!("Votes : {}", package.num_votes);
+    println!("Popularity : {:.2}", package.popularity);
+    println!("First Submitted : {}", package.first_submitted);
+    println!("Last Modified : {}", package.last_modified);
+    println!(
+        "Out-of-date : {}",
+        package
+            .out_of_date
+            .map_or_else(|| "No".to_string(), |date| date.to_string())
+    );
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    const SEARCH_RESPONSE: &str = r#"{"version":5,"type":"search","resultcount":2,"results":[
+        {"ID":2,"Name":"stub-b","PackageBaseID":2,"PackageBase":"stub-b","Version":"2.0-1",
+         "Description":"second","URL":null,"NumVotes":3,"Popularity":0.5,"OutOfDate":null,
+         "Maintainer":"someone","FirstSubmitted":1600000000,"LastModified":1600000001,
+         "URLPath":"/cgit/aur.git/snapshot/stub-b.tar.gz"},
+        {"ID":1,"Name":"stub-a","PackageBaseID":1,"PackageBase":"stub-a","Version":"1.0-1",
+         "Description":"first","URL":null,"NumVotes":1,"Popularity":0.1,"OutOfDate":null,
+         "Maintainer":null,"FirstSubmitted":1600000000,"LastModified":1600000001,
+         "URLPath":"/cgit/aur.git/snapshot/stub-a.tar.gz"}]}"#;
+
+    /// Answer a single HTTP request with `body`, the handle returns the request line
+    fn stub_server(body: &'static str) -> (AurConfig, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AurConfig {
+            url: format!("http://{}/rpc/", listener.local_addr().unwrap()),
+            ..AurConfig::default()
+        };
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+            }
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request_line
+        });
+        (config, server)
+    }
+
+    #[test]
+    fn search_uses_configured_url() {
+        let (config, server) = stub_server(SEARCH_RESPONSE);
+        let packages = search(&handle(&config), "stub", SearchBy::NameDesc).unwrap();
+
+        let names: Vec<&str> = packages.iter().map(|package| package.name.as_str()).collect();
+        assert_eq!(names, ["stub-a", "stub-b"]);
+        assert_eq!(packages[1].maintainer.as_deref(), Some("someone"));
+        assert!(server.join().unwrap().contains(" /rpc/"));
+    }
+
+    #[test]
+    fn info_uses_configured_url() {
+        let (config, server) = stub_server(SEARCH_RESPONSE);
+        let packages = info(&handle(&config), &["stub-a", "stub-b"]).unwrap();
+
+        assert_eq!(packages.len(), 2);
+        assert!(server.join().unwrap().contains(" /rpc/"));
+    }
+
+    #[test]
+    fn unreachable_rpc_is_an_error() {
+        // Bind and drop to get a port nothing listens on
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/rpc/", listener.local_addr().unwrap())
+        };
+        let config = AurConfig {
+            url,
+            ..AurConfig::default()
+        };
+        assert!(search(&handle(&config), "stub", SearchBy::NameDesc).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use alpm::{Alpm, Depend};
+use raur::{Package, Raur};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{aur::block_on, utils::satisfies};
+
+/// What has to be installed from the sync databases and built from the AUR
+pub struct BuildPlan {
+    /// Dependencies to install from the sync databases
+    pub repo: Vec<String>,
+    /// AUR packages in build order, dependencies first
+    pub aur: Vec<Package>,
+}
+
+impl BuildPlan {
+    pub fn print(&self) {
+        if !self.repo.is_empty() {
+            println!(
+                "Repo dependencies ({}) {}",
+                self.repo.len(),
+                self.repo.join("  ")
+            );
+        }
+        println!(
+            "AUR build order ({}) {}",
+            self.aur.len(),
+            self.aur
+                .iter()
+                .map(|package| format!("{}-{}", package.name, package.version))
+                .collect::<Vec<String>>()
+                .join(" -> ")
+        );
+        println!();
+    }
+}
+
+pub enum ResolveError<E> {
+    Rpc(E),
+    NotFound { dep: String, required_by: String },
+    Cycle(Vec<String>),
+}
+
+impl<E: fmt::Display> fmt::Display for ResolveError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Rpc(err) => write!(f, "failed to query the AUR: {}", err),
+            ResolveError::NotFound { dep, required_by } => write!(
+                f,
+                "unable to satisfy dependency '{}' required by {}",
+                dep, required_by
+            ),
+            ResolveError::Cycle(cycle) => {
+                write!(f, "dependency cycle detected: {}", cycle.join(" -> "))
+            }
+        }
+    }
+}
+
+/// The local and sync database lookups the resolver needs
+pub trait Databases {
+    /// Whether an installed package satisfies `dep`
+    fn is_installed(&self, dep: &str) -> bool;
+    /// The sync database package satisfying `dep`
+    fn repo_provider(&self, dep: &str) -> Option<String>;
+}
+
+impl Databases for Alpm {
+    fn is_installed(&self, dep: &str) -> bool {
+        self.localdb()
+            .pkgs()
+            .map_or(false, |packages| packages.find_satisfier(dep).is_some())
+    }
+
+    fn repo_provider(&self, dep: &str) -> Option<String> {
+        self.find_dbs_satisfier(self.syncdbs(), dep)
+            .map(|package| package.name().to_string())
+    }
+}
+
+/// Resolves the dependencies of AUR packages, preferring installed and repo packages
+pub struct Resolver<'a, R, D = Alpm> {
+    handle: &'a D,
+    raur: &'a R,
+    packages: HashMap<String, Package>,
+}
+
+impl<'a, R: Raur, D: Databases> Resolver<'a, R, D> {
+    pub fn new(handle: &'a D, raur: &'a R) -> Self {
+        Self {
+            handle,
+            raur,
+            packages: HashMap::new(),
+        }
+    }
+
+    pub fn resolve(mut self, targets: Vec<Package>) -> Result<BuildPlan, ResolveError<R::Err>> {
+        let names: Vec<String> = targets.iter().map(|package| package.name.clone()).collect();
+        for package in targets {
+            self.packages.insert(package.name.clone(), package);
+        }
+        self.fetch(names.clone())?;
+
+        let mut plan = BuildPlan {
+            repo: Vec::new(),
+            aur: Vec::new(),
+        };
+        let mut done = HashSet::new();
+        let mut stack = Vec::new();
+        for name in &names {
+            self.visit(name, &mut plan, &mut done, &mut stack)?;
+        }
+        Ok(plan)
+    }
+
+    /// Query the AUR level by level until every AUR dependency is known
+    fn fetch(&mut self, mut pending: Vec<String>) -> Result<(), ResolveError<R::Err>> {
+        while !pending.is_empty() {
+            let mut missing: Vec<String> = Vec::new();
+            for name in &pending {
+                for dep in depends(&self.packages[name]) {
+                    let dep_name = Depend::new(dep.as_str()).name().to_string();
+                    // A known package with the wrong version is reported by `visit`
+                    if self.handle.is_installed(dep)
+                        || self.handle.repo_provider(dep).is_some()
+                        || self.aur_provider(dep).is_some()
+                        || self.packages.contains_key(&dep_name)
+                        || missing.contains(&dep_name)
+                    {
+                        continue;
+                    }
+                    missing.push(dep_name);
+                }
+            }
+            if missing.is_empty() {
+                break;
+            }
+
+            let found = block_on(self.raur.info(&missing)).map_err(ResolveError::Rpc)?;
+            pending = found.iter().map(|package| package.name.clone()).collect();
+            for package in found {
+                self.packages.insert(package.name.clone(), package);
+            }
+        }
+        Ok(())
+    }
+
+    /// Depth first walk pushing each package after its dependencies
+    fn visit(
+        &self,
+        name: &str,
+        plan: &mut BuildPlan,
+        done: &mut HashSet<String>,
+        stack: &mut Vec<String>,
+    ) -> Result<(), ResolveError<R::Err>> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if let Some(position) = stack.iter().position(|visiting| visiting == name) {
+            let mut cycle = stack[position..].to_vec();
+            cycle.push(name.to_string());
+            return Err(ResolveError::Cycle(cycle));
+        }
+
+        stack.push(name.to_string());
+        let package = &self.packages[name];
+        for dep in depends(package) {
+            if self.handle.is_installed(dep) {
+                continue;
+            }
+            if let Some(provider) = self.handle.repo_provider(dep) {
+                if !plan.repo.contains(&provider) {
+                    plan.repo.push(provider);
+                }
+                continue;
+            }
+            match self.aur_provider(dep) {
+                Some(provider) => self.visit(provider, plan, done, stack)?,
+                None => {
+                    return Err(ResolveError::NotFound {
+                        dep: dep.clone(),
+                        required_by: name.to_string(),
+                    })
+                }
+            }
+        }
+        stack.pop();
+
+        done.insert(name.to_string());
+        plan.aur.push(package.clone());
+        Ok(())
+    }
+
+    /// The known AUR package satisfying `dep`, by name or by what it provides
+    fn aur_provider(&self, dep: &str) -> Option<&str> {
+        let dep = Depend::new(dep);
+        if let Some(package) = self.packages.get(dep.name()) {
+            if satisfies(&dep, &package.name, Some(&package.version)) {
+                return Some(package.name.as_str());
+            }
+        }
+        self.packages
+            .values()
+            .find(|package| {
+                package.provides.iter().any(|provide| {
+                    let provide = Depend::new(provide.as_str());
+                    satisfies(&dep, provide.name(), provide.version().map(|ver| ver.as_str()))
+                })
+            })
+            .map(|package| package.name.as_str())
+    }
+}
+
+fn depends(package: &Package) -> impl Iterator<Item = &String> {
+    package
+        .depends
+        .iter()
+        .chain(&package.make_depends)
+        .chain(&package.check_depends)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use raur::SearchBy;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Answers info requests from a fixed set of packages and records the queries
+    struct FakeRaur {
+        packages: Vec<Package>,
+        queried: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Raur for FakeRaur {
+        type Err = String;
+
+        async fn raw_info<S: AsRef<str> + Send + Sync>(
+            &self,
+            names: &[S],
+        ) -> Result<Vec<Package>, String> {
+            let mut queried = self.queried.lock().unwrap();
+            queried.extend(names.iter().map(|name| name.as_ref().to_string()));
+            Ok(self
+                .packages
+                .iter()
+                .filter(|package| names.iter().any(|name| name.as_ref() == package.name))
+                .cloned()
+                .collect())
+        }
+
+        async fn search_by<S: AsRef<str> + Send + Sync>(
+            &self,
+            _query: S,
+            _strategy: SearchBy,
+        ) -> Result<Vec<Package>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Installed packages and sync database providers by dependency name
+    #[derive(Default)]
+    struct FakeDatabases {
+        installed: Vec<&'static str>,
+        repo: Vec<&'static str>,
+    }
+
+    impl Databases for FakeDatabases {
+        fn is_installed(&self, dep: &str) -> bool {
+            let dep = Depend::new(dep);
+            self.installed.iter().any(|name| *name == dep.name())
+        }
+
+        fn repo_provider(&self, dep: &str) -> Option<String> {
+            let dep = Depend::new(dep);
+            self.repo
+                .iter()
+                .find(|name| **name == dep.name())
+                .map(|name| name.to_string())
+        }
+    }
+
+    fn package(name: &str, version: &str, depends: &[&str], provides: &[&str]) -> Package {
+        serde_json::from_value(json!({
+            "ID": 1,
+            "Name": name,
+            "PackageBaseID": 1,
+            "PackageBase": name,
+            "Version": version,
+            "Description": null,
+            "URL": null,
+            "NumVotes": 0,
+            "Popularity": 0.0,
+            "OutOfDate": null,
+            "Maintainer": null,
+            "FirstSubmitted": 0,
+            "LastModified": 0,
+            "URLPath": "",
+            "Depends": depends,
+            "Provides": provides,
+        }))
+        .unwrap()
+    }
+
+    fn resolve(
+        databases: &FakeDatabases,
+        aur: Vec<Package>,
+        targets: &[&str],
+    ) -> (Result<BuildPlan, ResolveError<String>>, Vec<String>) {
+        let targets = aur
+            .iter()
+            .filter(|package| targets.contains(&package.name.as_str()))
+            .cloned()
+            .collect();
+        let raur = FakeRaur {
+            packages: aur,
+            queried: Mutex::new(Vec::new()),
+        };
+        let plan = Resolver::new(databases, &raur).resolve(targets);
+        (plan, raur.queried.into_inner().unwrap())
+    }
+
+    fn build_order(plan: &BuildPlan) -> Vec<&str> {
+        plan.aur.iter().map(|package| package.name.as_str()).collect()
+    }
+
+    #[test]
+    fn dependencies_are_built_first() {
+        let aur = vec![
+            package("app", "1-1", &["lib-a", "installed"], &[]),
+            package("lib-a", "1-1", &["lib-b"], &[]),
+            package("lib-b", "1-1", &[], &[]),
+        ];
+        let databases = FakeDatabases {
+            installed: vec!["installed"],
+            ..FakeDatabases::default()
+        };
+        let plan = match resolve(&databases, aur, &["app"]).0 {
+            Ok(plan) => plan,
+            Err(err) => panic!("{}", err),
+        };
+
+        assert_eq!(build_order(&plan), ["lib-b", "lib-a", "app"]);
+        assert!(plan.repo.is_empty());
+    }
+
+    #[test]
+    fn repo_providers_are_preferred() {
+        let aur = vec![
+            package("app", "1-1", &["foo"], &[]),
+            package("foo", "1-1", &[], &[]),
+        ];
+        let databases = FakeDatabases {
+            repo: vec!["foo"],
+            ..FakeDatabases::default()
+        };
+        let (plan, queried) = resolve(&databases, aur, &["app"]);
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(err) => panic!("{}", err),
+        };
+
+        assert_eq!(plan.repo, ["foo"]);
+        assert_eq!(build_order(&plan), ["app"]);
+        assert!(!queried.contains(&"foo".to_string()));
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let aur = vec![
+            package("a", "1-1", &["b"], &[]),
+            package("b", "1-1", &["a"], &[]),
+        ];
+        match resolve(&FakeDatabases::default(), aur, &["a"]).0 {
+            Err(ResolveError::Cycle(cycle)) => assert_eq!(cycle, ["a", "b", "a"]),
+            Err(err) => panic!("{}", err),
+            Ok(plan) => panic!("resolved a cycle: {:?}", build_order(&plan)),
+        }
+    }
+
+    #[test]
+    fn version_constraints_are_checked() {
+        let aur = vec![
+            package("app", "1-1", &["lib>=2"], &[]),
+            package("lib", "1.0-1", &[], &[]),
+        ];
+        match resolve(&FakeDatabases::default(), aur, &["app"]).0 {
+            Err(ResolveError::NotFound { dep, required_by }) => {
+                assert_eq!(dep, "lib>=2");
+                assert_eq!(required_by, "app");
+            }
+            Err(err) => panic!("{}", err),
+            Ok(plan) => panic!("resolved an old version: {:?}", build_order(&plan)),
+        }
+    }
+
+    #[test]
+    fn versioned_provides_satisfy_constraints() {
+        let aur = vec![
+            package("app", "1-1", &["lib>=2"], &[]),
+            package("lib", "1.0-1", &[], &[]),
+            package("lib-git", "r10-1", &[], &["lib=2.5"]),
+        ];
+        let plan = match resolve(&FakeDatabases::default(), aur, &["app", "lib-git"]).0 {
+            Ok(plan) => plan,
+            Err(err) => panic!("{}", err),
+        };
+
+        assert_eq!(build_order(&plan), ["lib-git", "app"]);
+    }
+}
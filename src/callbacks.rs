@@ -1,7 +1,7 @@
 use {
     alpm::{
-        alpm_sys::*, Alpm, Event, EventType, HookWhen, LogLevel, PackageOperation, Progress,
-        Question,
+        alpm_sys::*, Alpm, Event, EventType, HookWhen, LogLevel, Package, PackageOperation,
+        Progress, Question,
     },
     dialoguer::{Confirm, Select},
     indicatif::{ProgressBar, ProgressStyle},
@@ -22,69 +22,80 @@ static mut PROGRESS_CALLBACK: Option<ProgressCallback> = None;
 
 static mut ALPM_HANDLE: *mut alpm_handle_t = ptr::null_mut();
 
-pub fn init(handle: &Alpm) {
+/// Clones of the callbacks' bars for the Ctrl+C thread. Written only by `init`,
+/// before the handler is installed, so that thread never sees the callbacks
+/// while the main thread updates them; the bars themselves are shared behind a lock.
+static mut BARS: Vec<ProgressBar> = Vec::new();
+
+/// Set up the callback state, questions are answered with their default on `no_confirm`
+pub fn init(handle: &Alpm, no_confirm: bool) {
     unsafe {
-        QUESTION_CALLBACK = Some(QuestionCallback::new());
+        QUESTION_CALLBACK = Some(QuestionCallback::new(no_confirm));
         LOG_CALLBACK = Some(LogCallback::new());
         DL_CALLBACK = Some(DlCallback::new(handle.syncdbs().count()));
         EVENT_CALLBACK = Some(EventCallback::new());
         PROGRESS_CALLBACK = Some(ProgressCallback::new());
 
         ALPM_HANDLE = handle.as_alpm_handle_t();
+
+        BARS = vec![
+            DL_CALLBACK.as_ref().unwrap().bar.clone(),
+            PROGRESS_CALLBACK.as_ref().unwrap().bar.clone(),
+        ];
     }
 }
 
-pub struct QuestionCallback;
+/// Clear any progress bar so the terminal is usable again
+pub fn clear_progress() {
+    unsafe {
+        for bar in BARS.iter() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+pub struct QuestionCallback {
+    no_confirm: bool,
+}
 
 impl QuestionCallback {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(no_confirm: bool) -> Self {
+        Self { no_confirm }
     }
 
+    /// Every question is answered, a panic here would unwind into libalpm
     pub fn update(&mut self, question: &mut Question) {
         match question {
-            Question::InstallIgnorepkg(question) => question.set_install(
-                Confirm::new()
-                    .with_prompt(format!(
-                        "{} is in IgnorePkg. Install anyway?",
-                        question.pkg().name()
-                    ))
-                    .interact()
-                    .unwrap(),
-            ),
-            Question::Replace(question) => question.set_replace(
-                Confirm::new()
-                    .with_prompt(format!(
-                        "Replace {} with {}/{}",
-                        question.oldpkg().name(),
-                        question.newdb().name(),
-                        question.newpkg().name()
-                    ))
-                    .interact()
-                    .unwrap(),
-            ),
-            Question::Conflict(question) => question.set_remove(
-                Confirm::new()
-                    .default(false)
-                    .with_prompt(format!(
-                        "{} and {} are in conflict. Remove {}?",
-                        question.conflict().package1(),
-                        question.conflict().package2(),
-                        question.conflict().package1()
-                    ))
-                    .interact()
-                    .unwrap(),
-            ),
-            Question::Corrupted(question) => question.set_remove(
-                Confirm::new()
-                    .with_prompt(format!(
-                        "File {} is corrupted ({}). Remove it?",
-                        question.filepath(),
-                        question.reason()
-                    ))
-                    .interact()
-                    .unwrap(),
-            ),
+            Question::InstallIgnorepkg(question) => question.set_install(self.ask(
+                format!("{} is in IgnorePkg. Install anyway?", question.pkg().name()),
+                true,
+            )),
+            Question::Replace(question) => question.set_replace(self.ask(
+                format!(
+                    "Replace {} with {}/{}",
+                    question.oldpkg().name(),
+                    question.newdb().name(),
+                    question.newpkg().name()
+                ),
+                true,
+            )),
+            Question::Conflict(question) => question.set_remove(self.ask(
+                format!(
+                    "{} and {} are in conflict. Remove {}?",
+                    question.conflict().package1(),
+                    question.conflict().package2(),
+                    question.conflict().package1()
+                ),
+                false,
+            )),
+            Question::Corrupted(question) => question.set_remove(self.ask(
+                format!(
+                    "File {} is corrupted ({}). Remove it?",
+                    question.filepath(),
+                    question.reason()
+                ),
+                true,
+            )),
             Question::RemovePkgs(question) => {
                 println!(
                     "The following package[s] cannot be upgraded due to unresolvable dependencies:"
@@ -93,48 +104,55 @@ impl QuestionCallback {
                     println!("{}", pkg.name());
                 }
 
-                question.set_skip(
-                    Confirm::new()
-                        .default(false)
-                        .with_prompt("Do you want to skip the above package for this upgrade?")
-                        .interact()
-                        .unwrap(),
-                );
+                question.set_skip(self.ask(
+                    "Do you want to skip the above package for this upgrade?".to_string(),
+                    false,
+                ));
             }
+            Question::SelectProvider(question) if self.no_confirm => question.set_index(0),
             Question::SelectProvider(question) => {
-                question.set_index(
-                    Select::new()
-                        .with_prompt(format!(
-                            "There are {} providers available for {}:",
-                            question.providers().count(),
-                            question.depend()
-                        ))
-                        .items(
-                            question
-                                .providers()
-                                .map(|pkg| pkg.name().to_string())
-                                .collect::<Vec<String>>()
-                                .as_slice(),
-                        )
-                        .interact()
-                        .unwrap()
-                        .try_into()
-                        .unwrap(),
-                );
-            }
-            Question::ImportKey(question) => question.set_import(
-                Confirm::new()
+                let index = Select::new()
                     .with_prompt(format!(
-                        "Import PGP key {} \"{}\"?",
-                        question.key().fingerprint(),
-                        question.key().uid()
+                        "There are {} providers available for {}:",
+                        question.providers().count(),
+                        question.depend()
                     ))
+                    .items(
+                        question
+                            .providers()
+                            .map(|pkg| pkg.name().to_string())
+                            .collect::<Vec<String>>()
+                            .as_slice(),
+                    )
+                    .default(0)
                     .interact()
-                    .unwrap(),
-            ),
+                    .unwrap_or(0);
+                question.set_index(index.try_into().unwrap_or(0));
+            }
+            Question::ImportKey(question) => question.set_import(self.ask(
+                format!(
+                    "Import PGP key {} \"{}\"?",
+                    question.key().fingerprint(),
+                    question.key().uid()
+                ),
+                true,
+            )),
         };
     }
 
+    /// Ask a yes/no question, falling back to pacman's `default` answer with
+    /// `--noconfirm` or when there is no terminal to ask on
+    fn ask(&self, prompt: String, default: bool) -> bool {
+        if self.no_confirm {
+            return default;
+        }
+        Confirm::new()
+            .default(default)
+            .with_prompt(prompt)
+            .interact()
+            .unwrap_or(default)
+    }
+
     pub fn register() {
         unsafe extern "C" fn c_questioncb(question: *mut alpm_question_t) {
             let mut question = Question::new(ALPM_HANDLE, question);
@@ -280,9 +298,9 @@ impl EventCallback {
                 _ => {}
             },
             Event::PackageOperation(event) => match event.operation() {
-                PackageOperation::Install(op) => todo!("Display optdepends"),
-                PackageOperation::Upgrade(new, old) => todo!("Display new optdepends"),
-                PackageOperation::Downgrade(new, old) => todo!("Display new optdepends"),
+                PackageOperation::Install(new) => print_optdepends(&new, None),
+                PackageOperation::Upgrade(new, old) => print_optdepends(&new, Some(&old)),
+                PackageOperation::Downgrade(new, old) => print_optdepends(&new, Some(&old)),
                 PackageOperation::Reinstall(_, _) => {}
                 PackageOperation::Remove(_) => {}
             },
@@ -296,8 +314,16 @@ impl EventCallback {
                 "database file for '{}' is missing (use '[S|F]y' to download)",
                 event.dbname()
             ),
-            Event::PacnewCreated(event) => todo!("Show pacnew and accumulate them"),
-            Event::PacsaveCreated(event) => todo!("Show pacsave and accumulate them"),
+            Event::PacnewCreated(event) => println!(
+                "warning: {} installed as {}.pacnew",
+                event.file(),
+                event.file()
+            ),
+            Event::PacsaveCreated(event) => println!(
+                "warning: {} saved as {}.pacsave",
+                event.file(),
+                event.file()
+            ),
             Event::PkgDownload(_) => {}
         }
     }
@@ -312,6 +338,30 @@ impl EventCallback {
     }
 }
 
+/// Print the optional dependencies of `new` that `old` did not have
+fn print_optdepends(new: &Package, old: Option<&Package>) {
+    let optdepends: Vec<String> = new
+        .optdepends()
+        .map(|dep| dep.to_string())
+        .filter(|dep| {
+            old.map_or(true, |old| {
+                old.optdepends().all(|old_dep| old_dep.to_string() != *dep)
+            })
+        })
+        .collect();
+    if optdepends.is_empty() {
+        return;
+    }
+    if old.is_some() {
+        println!("New optional dependencies for {}", new.name());
+    } else {
+        println!("Optional dependencies for {}", new.name());
+    }
+    for dep in optdepends {
+        println!("    {}", dep);
+    }
+}
+
 pub struct ProgressCallback {
     bar: ProgressBar,
     last_step: Option<Progress>,
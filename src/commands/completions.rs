@@ -1,16 +1,245 @@
 use alpm::Alpm;
-use clap::Clap;
-use std::cell::RefCell;
+use clap::{App, AppSettings, Clap, IntoApp};
+use clap_generate::{
+    generate,
+    generators::{Bash, Elvish, Fish, PowerShell, Zsh},
+    Generator,
+};
+use std::{cell::RefCell, io, path::Path};
 
-use crate::{commands::CommandHandler, config::Config};
+use crate::{
+    args::Args,
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+};
+
+const BIN_NAME: &str = "rpac";
 
 /// Auto-generated completions
 #[derive(Clap, Clone)]
 pub struct Command {
+    /// The shell to generate completions for (bash, zsh, fish, elvish or powershell)
     #[clap(env("SHELL"))]
     pub shell: String,
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, _alpm_handle: RefCell<Alpm>, _config: Config) {}
+    fn handle(&self, _alpm_handle: &RefCell<Alpm>, _config: Config) -> Result<()> {
+        // $SHELL holds a path like /bin/zsh
+        let shell = Path::new(&self.shell)
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_lowercase());
+        let mut app = Args::into_app();
+        let mut out = io::stdout();
+        match shell.as_str() {
+            "bash" => {
+                generate::<Bash, _>(&mut app, BIN_NAME, &mut out);
+                print!("{}", BASH_DYNAMIC);
+            }
+            "zsh" => {
+                generate::<Zsh, _>(&mut app, BIN_NAME, &mut out);
+                print!("{}", ZSH_DYNAMIC);
+            }
+            "fish" => {
+                generate::<Fish, _>(&mut app, BIN_NAME, &mut out);
+                print!("{}", FISH_DYNAMIC);
+            }
+            "elvish" => print_extended::<Elvish>(&mut app, ELVISH_DYNAMIC),
+            "powershell" | "pwsh" => print_extended::<PowerShell>(&mut app, POWERSHELL_DYNAMIC),
+            _ => {
+                return Err(Error::Failed(format!(
+                    "unsupported shell '{}', expected bash, zsh, fish, elvish or powershell",
+                    self.shell
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print the generated script with `dynamic` appended inside its completer block,
+/// which is the last block of the elvish and PowerShell scripts
+fn print_extended<G: Generator>(app: &mut App, dynamic: &str) {
+    let mut script = Vec::new();
+    generate::<G, _>(app, BIN_NAME, &mut script);
+    let script = String::from_utf8_lossy(&script);
+    let script = script.trim_end();
+    match script.strip_suffix('}') {
+        Some(open) => println!("{}{}}}", open, dynamic),
+        // Static completions are still better than none
+        None => println!("{}", script),
+    }
+}
+
+/// Print names for the shell completion scripts to complete targets with
+#[derive(Clap, Clone)]
+#[clap(setting = AppSettings::Hidden)]
+pub struct Complete {
+    #[clap(possible_values = &["installed", "packages", "repos", "groups"])]
+    pub kind: String,
+}
+
+impl CommandHandler for Complete {
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, _config: Config) -> Result<()> {
+        let handle = alpm_handle.borrow();
+        let mut names: Vec<&str> = Vec::new();
+        match self.kind.as_str() {
+            "installed" => names.extend(handle.localdb().pkgs()?.map(|package| package.name())),
+            "packages" => {
+                for db in handle.syncdbs() {
+                    names.extend(db.pkgs()?.map(|package| package.name()));
+                }
+            }
+            "repos" => names.extend(handle.syncdbs().map(|db| db.name())),
+            _ => {
+                names.extend(handle.localdb().groups()?.map(|group| group.name()));
+                for db in handle.syncdbs() {
+                    names.extend(db.groups()?.map(|group| group.name()));
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        for name in names {
+            println!("{}", name);
+        }
+        Ok(())
+    }
+}
+
+/// Completes targets through `rpac complete` on top of the generated `_rpac`
+const BASH_DYNAMIC: &str = r#"
+_rpac_dynamic() {
+    _rpac "$@"
+    local cur="${COMP_WORDS[COMP_CWORD]}" op="" kind="" word
+    [[ $cur == -* ]] && return
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        case "$word" in
+            -Q*|--query) op=Q ;;
+            -R*|--remove) op=R ;;
+            -S*|--sync) op=S ;;
+        esac
+        case "$op:$word" in
+            S:--list|S:-[!-]*l*) kind=repos ;;
+            S:--groups|S:-[!-]*g*) kind=groups ;;
+        esac
+    done
+    case "$op" in
+        Q|R) kind=installed ;;
+        S) kind="${kind:-packages}" ;;
+        *) return ;;
+    esac
+    COMPREPLY+=($(compgen -W "$(rpac complete "$kind" 2>/dev/null)" -- "$cur"))
 }
+complete -F _rpac_dynamic -o bashdefault -o default rpac
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+complete -c rpac -n '__fish_contains_opt -s Q query; or __fish_contains_opt -s R remove' -f -a '(rpac complete installed 2>/dev/null)'
+complete -c rpac -n '__fish_contains_opt -s S sync; and __fish_contains_opt -s l list' -f -a '(rpac complete repos 2>/dev/null)'
+complete -c rpac -n '__fish_contains_opt -s S sync; and __fish_contains_opt -s g groups' -f -a '(rpac complete groups 2>/dev/null)'
+complete -c rpac -n '__fish_contains_opt -s S sync; and not __fish_contains_opt -s l list -s g groups' -f -a '(rpac complete packages 2>/dev/null)'
+"#;
+
+/// Wraps the generated `_rpac`, the first completion already ran it when this is autoloaded
+const ZSH_DYNAMIC: &str = r#"
+_rpac_targets() {
+    local op="" kind="" word
+    [[ $PREFIX == -* ]] && return
+    # $words may have been shifted by _arguments, the line itself is untouched
+    for word in ${${(z)LBUFFER}[2,-1]}; do
+        case "$word" in
+            -Q*|--query) op=Q ;;
+            -R*|--remove) op=R ;;
+            -S*|--sync) op=S ;;
+        esac
+        case "$op:$word" in
+            S:--list|S:-[!-]*l*) kind=repos ;;
+            S:--groups|S:-[!-]*g*) kind=groups ;;
+        esac
+    done
+    case "$op" in
+        Q|R) kind=installed ;;
+        S) kind="${kind:-packages}" ;;
+        *) return ;;
+    esac
+    compadd -- ${(f)"$(rpac complete "$kind" 2>/dev/null)"}
+}
+
+(( $+functions[_rpac_generated] )) || functions[_rpac_generated]=$functions[_rpac]
+_rpac() {
+    _rpac_generated "$@"
+    _rpac_targets
+}
+
+_rpac_targets
+"#;
+
+const ELVISH_DYNAMIC: &str = r#"
+    use re
+    if (re:match '^-' $words[-1]) {
+        return
+    }
+    op = ''
+    kind = ''
+    for word $words[1:-1] {
+        if (re:match '^(-Q|--query$)' $word) {
+            op = Q
+        } elif (re:match '^(-R|--remove$)' $word) {
+            op = R
+        } elif (re:match '^(-S|--sync$)' $word) {
+            op = S
+        }
+        if (and (eq $op S) (re:match '^(--list|-[^-]*l.*)$' $word)) {
+            kind = repos
+        } elif (and (eq $op S) (re:match '^(--groups|-[^-]*g.*)$' $word)) {
+            kind = groups
+        }
+    }
+    if (or (eq $op Q) (eq $op R)) {
+        kind = installed
+    } elif (eq $op S) {
+        if (eq $kind '') {
+            kind = packages
+        }
+    } else {
+        return
+    }
+    rpac complete $kind 2>/dev/null | from-lines
+"#;
+
+const POWERSHELL_DYNAMIC: &str = r#"
+    if ($wordToComplete -like '-*') {
+        return
+    }
+    $op = ''
+    $kind = ''
+    foreach ($element in $commandAst.CommandElements | Select-Object -Skip 1) {
+        # Only the words before the one being completed
+        if ($element.Extent.EndOffset -ge $cursorPosition) {
+            break
+        }
+        $word = $element.ToString()
+        switch -Regex -CaseSensitive ($word) {
+            '^(-Q|--query$)' { $op = 'Q' }
+            '^(-R|--remove$)' { $op = 'R' }
+            '^(-S|--sync$)' { $op = 'S' }
+        }
+        if ($op -eq 'S' -and $word -cmatch '^(--list|-[^-]*l.*)$') {
+            $kind = 'repos'
+        } elseif ($op -eq 'S' -and $word -cmatch '^(--groups|-[^-]*g.*)$') {
+            $kind = 'groups'
+        }
+    }
+    switch ($op) {
+        { $_ -in 'Q', 'R' } { $kind = 'installed' }
+        'S' { if (-not $kind) { $kind = 'packages' } }
+        default { return }
+    }
+    rpac complete $kind 2>$null |
+        Where-Object { $_ -like "$wordToComplete*" } |
+        ForEach-Object {
+            [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_)
+        }
+"#;
@@ -0,0 +1,44 @@
+use alpm::Alpm;
+use clap::Clap;
+use std::{cell::RefCell, path::PathBuf};
+
+use crate::{
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+    pacman_conf,
+};
+
+/// Configuration utilities
+#[derive(Clap, Clone)]
+pub struct Command {
+    #[clap(subcommand)]
+    pub action: Action,
+}
+
+#[derive(Clap, Clone)]
+pub enum Action {
+    /// Print a pacman.conf as an equivalent rpac configuration
+    Convert(Convert),
+}
+
+#[derive(Clap, Clone)]
+pub struct Convert {
+    /// The pacman.conf to convert
+    #[clap(default_value = "/etc/pacman.conf", parse(from_os_str))]
+    pub file: PathBuf,
+}
+
+impl CommandHandler for Command {
+    fn handle(&self, _alpm_handle: &RefCell<Alpm>, _config: Config) -> Result<()> {
+        match &self.action {
+            Action::Convert(convert) => {
+                let config = pacman_conf::parse(&convert.file)?;
+                let converted = toml::to_string(&config)
+                    .map_err(|err| Error::Failed(format!("could not convert config: {}", err)))?;
+                print!("{}", converted);
+            }
+        }
+        Ok(())
+    }
+}
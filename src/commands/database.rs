@@ -1,8 +1,13 @@
-use alpm::{Alpm, PackageReason, TransFlag};
+use alpm::{Alpm, Depend, Package, PackageReason, TransFlag};
 use clap::Clap;
-use std::{cell::RefCell, fs, path::PathBuf};
+use std::{cell::RefCell, collections::HashMap, fs, path::PathBuf};
 
-use crate::{commands::CommandHandler, config::Config};
+use crate::{
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+    utils::{satisfies, transaction},
+};
 
 /// Database operations
 #[derive(Clap, Clone)]
@@ -24,32 +29,145 @@ pub struct Command {
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, config: Config) {
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, _config: Config) -> Result<()> {
         if self.check >= 1 {
             let handle = alpm_handle.borrow();
-            let db_path = handle.dbpath();
-            for entry in fs::read_dir(PathBuf::from(db_path).join("local")).unwrap() {
-                let entry = entry.unwrap();
-                if entry.file_name() == "ALPM_DB_VERSION" {
-                    continue;
-                } else {
-                    assert!(entry.path().join("desc").exists());
-                    assert!(entry.path().join("files").exists())
-                }
-                todo!("Check package and file conflicts");
+            let errors = if self.check >= 2 {
+                check_sync(&handle)?
+            } else {
+                check_local(&handle)?
+            };
+            if errors > 0 {
+                return Err(Error::Failed(format!(
+                    "{} database errors have been found",
+                    errors
+                )));
+            }
+            if !self.quiet {
+                println!("No database errors have been found!");
             }
         }
         if self.asdeps || self.asexplicit {
-            let handle = alpm_handle.borrow_mut();
             let reason = if self.asdeps {
                 PackageReason::Depend
             } else {
                 PackageReason::Explicit
             };
-            let _ = handle.trans_init(TransFlag::empty()).unwrap();
-            for mut pkg in handle.localdb().pkgs().unwrap() {
-                pkg.set_reason(reason).unwrap();
+            transaction(
+                &mut alpm_handle.borrow_mut(),
+                TransFlag::empty(),
+                |handle| {
+                    let mut missing = Vec::new();
+                    for name in &self.packages {
+                        match handle.localdb().pkg(name.as_str()) {
+                            Ok(mut package) => package.set_reason(reason)?,
+                            Err(_) => missing.push(name.clone()),
+                        }
+                    }
+                    if missing.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(Error::TargetNotFound(missing))
+                    }
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Like `pacman -Dk`: database entries, dependencies, conflicts and file ownership
+fn check_local(handle: &Alpm) -> Result<usize> {
+    let mut errors = 0;
+    for entry in fs::read_dir(PathBuf::from(handle.dbpath()).join("local"))? {
+        let entry = entry?;
+        if entry.file_name() == "ALPM_DB_VERSION" {
+            continue;
+        }
+        for file in &["desc", "files"] {
+            if !entry.path().join(file).exists() {
+                eprintln!(
+                    "{}: missing {} file",
+                    entry.file_name().to_string_lossy(),
+                    file
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    let installed = handle.localdb().pkgs()?;
+    let packages: Vec<Package> = handle.localdb().pkgs()?.collect();
+    errors += missing_dependencies(&packages, |dep| {
+        installed.find_satisfier(dep.to_string()).is_some()
+    });
+    errors += conflicts(&packages);
+
+    // Directories are shared, any other path has a single owner
+    let mut owners = HashMap::new();
+    for package in &packages {
+        for file in package.files().files() {
+            if file.name().ends_with('/') {
+                continue;
+            }
+            if let Some(owner) = owners.insert(file.name().to_string(), package.name()) {
+                eprintln!(
+                    "file owned by '{}' and '{}': '{}'",
+                    owner,
+                    package.name(),
+                    file.name()
+                );
+                errors += 1;
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Like `pacman -Dkk`: the dependencies of every sync package
+fn check_sync(handle: &Alpm) -> Result<usize> {
+    let mut packages = Vec::new();
+    for db in handle.syncdbs() {
+        packages.extend(db.pkgs()?);
+    }
+    Ok(missing_dependencies(&packages, |dep| {
+        handle
+            .find_dbs_satisfier(handle.syncdbs(), dep.to_string())
+            .is_some()
+    }))
+}
+
+fn missing_dependencies(packages: &[Package], satisfied: impl Fn(&Depend) -> bool) -> usize {
+    let mut errors = 0;
+    for package in packages {
+        for dep in package.depends().filter(|dep| !satisfied(dep)) {
+            eprintln!("missing '{}' dependency for '{}'", dep, package.name());
+            errors += 1;
+        }
+    }
+    errors
+}
+
+fn conflicts(packages: &[Package]) -> usize {
+    let mut errors = 0;
+    for package in packages {
+        for conflict in package.conflicts() {
+            // Packages often conflict with what they provide themselves
+            let others = packages
+                .iter()
+                .filter(|other| other.name() != package.name());
+            for other in others {
+                let version = other.version();
+                let provided = other.provides().any(|provide| {
+                    let version = provide.version().map(|ver| ver.as_str());
+                    satisfies(&conflict, provide.name(), version)
+                });
+                if provided || satisfies(&conflict, other.name(), Some(version.as_str())) {
+                    eprintln!("'{}' conflicts with '{}'", package.name(), other.name());
+                    errors += 1;
+                }
             }
         }
     }
+    errors
 }
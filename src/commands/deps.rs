@@ -0,0 +1,203 @@
+use alpm::{Alpm, Db, Package};
+use clap::Clap;
+use std::{cell::RefCell, collections::HashSet};
+
+use crate::{
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+};
+
+/// Show the dependency tree of installed packages
+#[derive(Clap, Clone)]
+pub struct Command {
+    /// Show the packages depending on the targets instead
+    #[clap(short, long)]
+    pub reverse: bool,
+    /// Include optional dependencies
+    #[clap(short, long)]
+    pub optional: bool,
+    /// Stop descending after this many levels
+    #[clap(short, long)]
+    pub depth: Option<usize>,
+    /// Print a Graphviz DOT graph instead of a tree
+    #[clap(long)]
+    pub dot: bool,
+    /// The installed packages to start from
+    #[clap(required = true)]
+    pub targets: Vec<String>,
+}
+
+/// A dependency (or dependent with `--reverse`) of a package
+struct Edge<'a> {
+    /// The dependency name, without a version constraint
+    name: String,
+    label: String,
+    package: Option<Package<'a>>,
+    optional: bool,
+}
+
+impl CommandHandler for Command {
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, _config: Config) -> Result<()> {
+        let handle = alpm_handle.borrow();
+        let localdb = handle.localdb();
+        let mut packages = Vec::new();
+        let mut missing = Vec::new();
+        for target in &self.targets {
+            match localdb.pkg(target.as_str()) {
+                Ok(package) => packages.push(package),
+                Err(_) => missing.push(target.clone()),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::TargetNotFound(missing));
+        }
+
+        // Packages are expanded only once, later occurrences are marked
+        let mut seen = HashSet::new();
+        if self.dot {
+            println!("digraph dependencies {{");
+            for package in packages {
+                seen.insert(package.name().to_string());
+                self.print_dot(&localdb, package, 0, &mut seen)?;
+            }
+            println!("}}");
+        } else {
+            for package in packages {
+                println!("{} {}", package.name(), package.version());
+                seen.insert(package.name().to_string());
+                self.print_tree(&localdb, package, "", 0, &mut seen)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Command {
+    fn edges<'a>(&self, localdb: &Db<'a>, package: Package<'a>) -> Result<Vec<Edge<'a>>> {
+        let mut edges = Vec::new();
+        if self.reverse {
+            let dependents = package
+                .required_by()
+                .into_iter()
+                .map(|name| (name, false))
+                .chain(
+                    package
+                        .optional_for()
+                        .into_iter()
+                        .filter(|_| self.optional)
+                        .map(|name| (name, true)),
+                );
+            for (name, optional) in dependents {
+                edges.push(Edge {
+                    package: localdb.pkg(name.as_str()).ok(),
+                    label: name.clone(),
+                    name,
+                    optional,
+                });
+            }
+        } else {
+            let installed = localdb.pkgs()?;
+            for dep in package.depends() {
+                edges.push(Edge {
+                    name: dep.name().to_string(),
+                    label: dep.to_string(),
+                    package: installed.find_satisfier(dep.to_string()),
+                    optional: false,
+                });
+            }
+            if self.optional {
+                for dep in package.optdepends() {
+                    edges.push(Edge {
+                        name: dep.name().to_string(),
+                        label: dep.name().to_string(),
+                        package: installed.find_satisfier(dep.name()),
+                        optional: true,
+                    });
+                }
+            }
+        }
+        Ok(edges)
+    }
+
+    fn print_tree<'a>(
+        &self,
+        localdb: &Db<'a>,
+        package: Package<'a>,
+        prefix: &str,
+        depth: usize,
+        seen: &mut HashSet<String>,
+    ) -> Result<()> {
+        if self.depth.map_or(false, |max| depth >= max) {
+            return Ok(());
+        }
+        let edges = self.edges(localdb, package)?;
+        let count = edges.len();
+        for (i, edge) in edges.into_iter().enumerate() {
+            let last = i + 1 == count;
+            let branch = if last { "└─" } else { "├─" };
+            let mut line = format!("{}{}{}", prefix, branch, edge.label);
+            if edge.optional {
+                line.push_str(" (optional)");
+            }
+            let package = match edge.package {
+                Some(package) => package,
+                None => {
+                    println!("{} (not installed)", line);
+                    continue;
+                }
+            };
+            if edge.name != package.name() {
+                line.push_str(&format!(" provided by {}", package.name()));
+            }
+            if !seen.insert(package.name().to_string()) {
+                println!("{} (*)", line);
+                continue;
+            }
+            println!("{}", line);
+            let prefix = format!("{}{}", prefix, if last { "  " } else { "│ " });
+            self.print_tree(localdb, package, &prefix, depth + 1, seen)?;
+        }
+        Ok(())
+    }
+
+    fn print_dot<'a>(
+        &self,
+        localdb: &Db<'a>,
+        package: Package<'a>,
+        depth: usize,
+        seen: &mut HashSet<String>,
+    ) -> Result<()> {
+        if self.depth.map_or(false, |max| depth >= max) {
+            return Ok(());
+        }
+        let name = package.name().to_string();
+        for edge in self.edges(localdb, package)? {
+            let target = edge
+                .package
+                .as_ref()
+                .map_or_else(|| edge.label.clone(), |package| package.name().to_string());
+            // Edges always point from the dependent package to its dependency
+            let (from, to) = if self.reverse {
+                (&target, &name)
+            } else {
+                (&name, &target)
+            };
+            let style = if edge.optional {
+                " [style=dashed]"
+            } else {
+                ""
+            };
+            println!("    {:?} -> {:?}{};", from, to, style);
+            match edge.package {
+                None => println!("    {:?} [color=red];", target),
+                Some(package) => {
+                    if seen.insert(package.name().to_string()) {
+                        self.print_dot(localdb, package, depth + 1, seen)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
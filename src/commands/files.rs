@@ -3,7 +3,13 @@ use clap::Clap;
 use regex::RegexSet;
 use std::cell::RefCell;
 
-use crate::{commands::CommandHandler, config::Config};
+use crate::{
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+    output::{file_names, FilesRecord, Output},
+    utils::refresh_databases,
+};
 
 /// Files operations
 #[derive(Clap, Clone)]
@@ -23,19 +29,28 @@ pub struct Command {
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, _config: Config) {
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, config: Config) -> Result<()> {
         if self.refresh >= 1 {
-            let mut handle = alpm_handle.borrow_mut();
-            for mut db in handle.syncdbs_mut() {
-                db.update(!matches!(self.refresh, 1)).unwrap();
-            }
+            refresh_databases(&mut alpm_handle.borrow_mut(), self.refresh >= 2)?;
         }
 
+        let mut output = Output::new(config.output);
         if self.list {
             let handle = alpm_handle.borrow();
             for package_name in &self.targets {
                 let packages = handle.syncdbs().filter_map(|db| db.pkg(package_name).ok());
                 for package in packages {
+                    if !output.is_text() {
+                        let files = package.files();
+                        output.emit(&FilesRecord {
+                            repository: package.db().map_or("", |db| db.name()).to_string(),
+                            name: package.name().to_string(),
+                            version: package.version().to_string(),
+                            installed: handle.localdb().pkg(package.name()).is_ok(),
+                            files: file_names(files.files().iter().map(|file| file.name())),
+                        });
+                        continue;
+                    }
                     for file in package.files().files() {
                         println!("{}: {}", package.name(), file.name());
                     }
@@ -43,9 +58,10 @@ impl CommandHandler for Command {
             }
         } else if !self.targets.is_empty() {
             let handle = alpm_handle.borrow();
-            let regex = RegexSet::new(&self.targets).unwrap();
+            let regex = RegexSet::new(&self.targets)
+                .map_err(|err| Error::Failed(format!("invalid regular expression: {}", err)))?;
             for db in handle.syncdbs() {
-                for pkg in db.pkgs().unwrap() {
+                for pkg in db.pkgs()? {
                     let file_list = pkg.files();
 
                     let found: Vec<&str> = file_list
@@ -60,25 +76,34 @@ impl CommandHandler for Command {
                         })
                         .collect();
 
-                    if !found.is_empty() {
-                        println!(
-                            "{}/{} {} {}",
-                            db.name(),
-                            pkg.name(),
-                            pkg.version(),
-                            handle
-                                .localdb()
-                                .pkg(pkg.name())
-                                .map_or_else(|_| "", |_| "[installed]")
-                        );
-                        for file in found {
-                            println!("  {}", file);
-                        }
+                    if found.is_empty() {
+                        continue;
+                    }
+                    let installed = handle.localdb().pkg(pkg.name()).is_ok();
+                    if !output.is_text() {
+                        output.emit(&FilesRecord {
+                            repository: db.name().to_string(),
+                            name: pkg.name().to_string(),
+                            version: pkg.version().to_string(),
+                            installed,
+                            files: file_names(found),
+                        });
+                        continue;
+                    }
+                    println!(
+                        "{}/{} {} {}",
+                        db.name(),
+                        pkg.name(),
+                        pkg.version(),
+                        if installed { "[installed]" } else { "" }
+                    );
+                    for file in found {
+                        println!("  {}", file);
                     }
                 }
             }
-        } else {
-            unreachable!()
         }
+        output.finish();
+        Ok(())
     }
 }
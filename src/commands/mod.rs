@@ -1,5 +1,7 @@
 mod completions;
+mod config;
 mod database;
+mod deps;
 mod files;
 mod query;
 mod remove;
@@ -11,7 +13,7 @@ use clap::Clap;
 use enum_dispatch::enum_dispatch;
 use std::cell::RefCell;
 
-use crate::config::Config;
+use crate::{config::Config, error::Result};
 
 #[enum_dispatch]
 #[derive(Clap, Clone)]
@@ -30,9 +32,12 @@ pub enum Command {
     Upgrade(upgrade::Command),
     #[clap(long_flag = "completions")]
     Completions(completions::Command),
+    Complete(completions::Complete),
+    Config(config::Command),
+    Deps(deps::Command),
 }
 
 #[enum_dispatch(Command)]
 pub trait CommandHandler {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, config: Config);
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, config: Config) -> Result<()>;
 }
@@ -1,13 +1,21 @@
-use alpm::{Alpm, AlpmList, Db, Package, PackageReason, Usage};
+use alpm::{Alpm, AlpmList, Db, Package, PackageFrom, PackageReason, Usage};
 use clap::Clap;
-use path_absolutize::Absolutize;
 use std::{
     cell::RefCell,
-    io::Read,
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Read},
     path::{Path, PathBuf},
 };
 
-use crate::{commands::CommandHandler, config::Config, utils::{Join,EnumFormatter,check}};
+use crate::{
+    aur,
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+    output::{file_names, Output, OutputFormat, OwnershipRecord, PackageRecord},
+    owners::{self, OwnerIndex},
+    utils::{check, orphans, EnumFormatter, Join},
+};
 
 /// Query operations
 #[derive(Clap, Clone)]
@@ -46,7 +54,7 @@ pub struct Command {
     /// List installed packages only found in sync db(s) [filter]
     #[clap(short, long, conflicts_with = "foreign")]
     pub native: bool,
-    /// Query the packages that owns <file>
+    /// Query the packages that owns <file> (`-` reads files from stdin)
     #[clap(
         short,
         long,
@@ -63,7 +71,7 @@ pub struct Command {
         conflicts_with = "foreign"
     )]
     pub owns: bool,
-    /// Query a package file instead of the database
+    /// Query a package file instead of the database (-pi, -pl, -pc, -pk)
     #[clap(
         short = 'p',
         long,
@@ -106,20 +114,51 @@ pub struct Command {
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, config: Config) {
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, config: Config) -> Result<()> {
+        // Their results are only printed as text, a JSON consumer would not notice failed checks
+        if config.output != OutputFormat::Text && (self.check > 0 || self.changelog) {
+            return Err(Error::Failed(
+                "--check and --changelog only support --output text".to_string(),
+            ));
+        }
         if self.foreign || self.native || self.upgrade {
             let handle = alpm_handle.borrow();
-            let mut should_return = false;
+            let mut invalid = Vec::new();
             for db in handle.syncdbs() {
                 if let Err(err) = db.is_valid() {
-                    println!("database '{}' is not valid ({})",db.name(),err);
-                    should_return = true;
+                    eprintln!("database '{}' is not valid ({})", db.name(), err);
+                    invalid.push(db.name());
                 }
             }
-            if should_return {
-                return;
+            if !invalid.is_empty() {
+                return Err(Error::Failed(format!(
+                    "invalid databases: {}",
+                    invalid.join(", ")
+                )));
             }
         }
+        let aur_updates: HashMap<String, String> = if self.upgrade {
+            match aur::updates(&aur::handle(&config.aur), &alpm_handle.borrow()) {
+                Ok(updates) => updates
+                    .into_iter()
+                    .map(|package| (package.name, package.version))
+                    .collect(),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+        // -Qdt walks the dependency graph so orphaned cycles are listed as well
+        let orphans = if self.deps && self.unrequired > 0 {
+            orphans(&*alpm_handle.borrow(), self.unrequired == 1)?
+        } else {
+            HashSet::new()
+        };
+        let mut output = Output::new(config.output);
+        let mut missing = Vec::new();
         match self {
             _search if self.search => {
                 let handle = alpm_handle.borrow();
@@ -128,8 +167,10 @@ impl CommandHandler for Command {
                 } else {
                     handle.localdb().search(&self.targets)
                 };
-                for package in packages.unwrap() {
-                    if self.quiet {
+                for package in packages? {
+                    if !output.is_text() {
+                        output.emit(&PackageRecord::from_alpm(&package));
+                    } else if self.quiet {
                         println!("{}", package.name())
                     } else {
                         print!(
@@ -145,7 +186,7 @@ impl CommandHandler for Command {
                             println!(" ({})", groups.collect::<Vec<&str>>().join(" "));
                         }
 
-                        println!("    {}", package.desc().unwrap());
+                        println!("    {}", package.desc().map_or("None", |desc| desc));
                     }
                 }
             }
@@ -154,14 +195,13 @@ impl CommandHandler for Command {
                 if self.targets.is_empty() {
                     let localdb = handle.localdb();
                     let groups = localdb
-                        .groups()
-                        .unwrap()
+                        .groups()?
                         .map(|group| (group.packages(), group.name()));
 
                     for (packages, group) in groups {
-                        for package in packages
-                            .filter(|package| self.filter_package(package, handle.syncdbs()))
-                        {
+                        for package in packages.filter(|package| {
+                            self.filter_package(package, handle.syncdbs(), &aur_updates, &orphans)
+                        }) {
                             println!("{} {}", group, package.name())
                         }
                     }
@@ -174,9 +214,9 @@ impl CommandHandler for Command {
                         .map(|group| (group.packages(), group.name()));
 
                     for (packages, group) in groups {
-                        for package in packages
-                            .filter(|package| self.filter_package(package, handle.syncdbs()))
-                        {
+                        for package in packages.filter(|package| {
+                            self.filter_package(package, handle.syncdbs(), &aur_updates, &orphans)
+                        }) {
                             if self.quiet {
                                 println!("{}", package.name())
                             } else {
@@ -186,74 +226,149 @@ impl CommandHandler for Command {
                     }
                 }
             }
+            _file if self.file.is_some() => {
+                let handle = alpm_handle.borrow();
+                let siglevel = handle.local_file_siglevel();
+                // Further targets are package files as well
+                let paths = self
+                    .file
+                    .iter()
+                    .cloned()
+                    .chain(self.targets.iter().map(PathBuf::from));
+                for path in paths {
+                    let package = handle
+                        .pkg_load(path.to_string_lossy().as_ref(), true, siglevel)
+                        .map_err(|err| {
+                            Error::Failed(format!("could not load '{}': {}", path.display(), err))
+                        })?;
+                    self.display_package(&package, &handle, &aur_updates, &mut output)?;
+                }
+            }
             _all if self.targets.is_empty() => {
-                if self.file.is_some() || self.owns {
-                    eprintln!("NO");
+                if self.owns {
+                    return Err(Error::Failed("no targets specified".to_string()));
                 } else {
                     let handle = alpm_handle.borrow();
                     for package in handle
                         .localdb()
-                        .pkgs()
-                        .unwrap()
-                        .filter(|pkg| self.filter_package(pkg, handle.syncdbs()))
+                        .pkgs()?
+                        .filter(|pkg| {
+                            self.filter_package(pkg, handle.syncdbs(), &aur_updates, &orphans)
+                        })
                     {
-                        self.display_package(&package, &handle)
+                        self.display_package(&package, &handle, &aur_updates, &mut output)?;
                     }
                 }
             }
             _owns if self.owns => {
                 let handle = alpm_handle.borrow();
-                let files = self.targets.clone().into_iter().filter_map(|target| {
-                    let path = PathBuf::from(target);
+                let index = OwnerIndex::load(&handle)?;
+                let root = Path::new(handle.root());
+                let mut unowned = Vec::new();
 
-                    if let Ok(resolved) = path.absolutize() {
-                        if resolved.exists() {
-                            Some(resolved.into_owned())
-                        } else {
-                            resolve_path(path)
-                        }
+                for target in self.owns_targets()? {
+                    let path = PathBuf::from(&target);
+                    // Bare command names are looked up in $PATH like `which`
+                    let file = if path.exists() || target.contains('/') {
+                        owners::normalize(&path)
+                    } else if let Some(resolved) = resolve_path(&path) {
+                        owners::normalize(&resolved)
                     } else {
-                        resolve_path(path)
-                    }
-                });
+                        eprintln!("failed to find '{}' in PATH", target);
+                        unowned.push(target);
+                        continue;
+                    };
 
-                for file in files {
-                    for package in handle.localdb().pkgs().unwrap() {
-                        let is_owned = package
-                            .files()
-                            .files()
-                            .iter()
-                            .map(|file| PathBuf::from(format!("{}{}", handle.root(), file.name())))
-                            .any(|package_file| package_file == file);
-                        if is_owned {
+                    let owners = index.owners(root, &file);
+                    if owners.is_empty() {
+                        unowned.push(target);
+                    }
+                    for owner in owners {
+                        if !output.is_text() {
+                            output.emit(&OwnershipRecord {
+                                path: file.display().to_string(),
+                                package: owner.name.clone(),
+                                version: owner.version.clone(),
+                            });
+                        } else if self.quiet {
+                            println!("{}", owner.name)
+                        } else {
                             println!(
                                 "{} is owned by {} {}",
                                 file.display(),
-                                package.name(),
-                                package.version()
+                                owner.name,
+                                owner.version
                             )
                         }
                     }
                 }
+                if !unowned.is_empty() {
+                    output.finish();
+                    return Err(Error::Failed(format!(
+                        "no package owns {}",
+                        unowned.join(", ")
+                    )));
+                }
             }
             _ => {
                 let handle = alpm_handle.borrow();
-                let packages = self
-                    .targets
-                    .iter()
-                    .filter_map(|target| handle.localdb().pkg(target).ok())
-                    .filter(|pkg| self.filter_package(pkg,handle.syncdbs()));
-                for package in packages {
-                    self.display_package(&package, &handle)
+                for target in &self.targets {
+                    match handle.localdb().pkg(target) {
+                        Ok(package) => {
+                            if self.filter_package(
+                                &package,
+                                handle.syncdbs(),
+                                &aur_updates,
+                                &orphans,
+                            ) {
+                                self.display_package(
+                                    &package,
+                                    &handle,
+                                    &aur_updates,
+                                    &mut output,
+                                )?;
+                            }
+                        }
+                        Err(_) => missing.push(target.clone()),
+                    }
                 }
             }
         }
-
+        output.finish();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TargetNotFound(missing))
+        }
     }
 }
 
 impl Command {
-    fn filter_package(&self, package: &Package, sync_dbs: AlpmList<Db>) -> bool {
+    /// The `-Qo` targets, a `-` is replaced by the lines of stdin
+    fn owns_targets(&self) -> io::Result<Vec<String>> {
+        let mut targets = Vec::new();
+        for target in &self.targets {
+            if target == "-" {
+                for line in io::stdin().lock().lines() {
+                    let line = line?;
+                    if !line.trim().is_empty() {
+                        targets.push(line.trim().to_string());
+                    }
+                }
+            } else {
+                targets.push(target.clone());
+            }
+        }
+        Ok(targets)
+    }
+
+    fn filter_package(
+        &self,
+        package: &Package,
+        sync_dbs: AlpmList<Db>,
+        aur_updates: &HashMap<String, String>,
+        orphans: &HashSet<String>,
+    ) -> bool {
         if self.explicit && package.reason() != PackageReason::Explicit
             || self.deps && package.reason() != PackageReason::Depend
         {
@@ -261,17 +376,44 @@ impl Command {
         } else if let Some(locality) = PackageLocality::new(self.native, self.foreign) {
             locality == compute_locality(package, sync_dbs)
         } else if self.unrequired >= 1 {
-            if self.unrequired == 1 {
+            if self.deps {
+                orphans.contains(package.name())
+            } else if self.unrequired == 1 {
                 package.required_by().is_empty() && package.optional_for().is_empty()
             } else {
                 package.required_by().is_empty()
             }
         } else {
-            !(self.upgrade && package.sync_new_version(sync_dbs).is_some())
+            !self.upgrade
+                || aur_updates.contains_key(package.name())
+                || package.sync_new_version(sync_dbs).is_some()
         }
     }
 
-    fn display_package(&self, package: &Package, handle: &Alpm) {
+    fn display_package(
+        &self,
+        package: &Package,
+        handle: &Alpm,
+        aur_updates: &HashMap<String, String>,
+        output: &mut Output,
+    ) -> Result<()> {
+        if !output.is_text() {
+            let mut record = PackageRecord::from_alpm(package);
+            if self.list {
+                record.files = Some(file_names(
+                    package.files().files().iter().map(|file| file.name()),
+                ));
+            }
+            record.new_version = aur_updates.get(package.name()).cloned().or_else(|| {
+                package
+                    .sync_new_version(handle.syncdbs())
+                    .filter(|_| self.upgrade)
+                    .map(|new| new.version().to_string())
+            });
+            output.emit(&record);
+            return Ok(());
+        }
+
         if self.info > 0 {
             println!("Name : {}", package.name());
             println!("Version : {}", package.version());
@@ -320,7 +462,9 @@ impl Command {
             if let Some(date) = package.install_date() {
                 println!("Install Date : {}", date);
             }
-            println!("Install Reason : {}",EnumFormatter::from(package.reason()));
+            if package.origin() != PackageFrom::File {
+                println!("Install Reason : {}",EnumFormatter::from(package.reason()));
+            }
             println!("Install Script : {}", package.has_scriptlet());
             println!("Validated By : {}", EnumFormatter::from(package.validation()));
             println!();
@@ -339,43 +483,57 @@ impl Command {
             if let Ok(mut changelog) = package.changelog() {
                 println!("Changelog for {}:", package.name());
                 let mut changelog_string = String::new();
-                changelog.read_to_string(&mut changelog_string).unwrap();
+                changelog.read_to_string(&mut changelog_string)?;
                 println!("{}", changelog_string);
             }
         }
         if self.check > 0 {
-            let errors = if self.check == 1 {
-                check(false, package, &handle)
-            } else {
-                check(true, package, &handle)
-            };
+            let mut full = self.check > 1;
+            if package.origin() == PackageFrom::File {
+                let installed = handle.localdb().pkg(package.name()).ok();
+                let installed_version = installed.as_ref().map(|installed| installed.version());
+                if installed_version != Some(package.version()) {
+                    println!(
+                        "{}: {} differs from the installed version ({})",
+                        package.name(),
+                        package.version(),
+                        installed_version.map_or("not installed", |version| version.as_str())
+                    );
+                    // The mtree in the local database describes the installed version
+                    full = false;
+                }
+            }
+            let errors = check(full, package, &handle);
 
             if errors != 0 {
                 println!("{}: {} total files",package.name(), package.files().files().len());
-                println!("{} missing files",errors);
+                if !full {
+                    println!("{} missing files",errors);
+                } else {
+                    println!("{} altered files",errors);
+                }
             }
         }
 
-        if !self.info > 0 && !self.list && !self.changelog && !self.check > 0 {
+        if self.info == 0 && !self.list && !self.changelog && self.check == 0 {
             if !self.quiet {
                 print!("{} {}", package.name(), package.version());
-                if self.upgrade {
-                    print!(
-                        " -> {}",
-                        package
-                            .sync_new_version(handle.syncdbs())
-                            .unwrap()
-                            .version()
-                    );
+                if let Some(version) = aur_updates.get(package.name()) {
+                    print!(" -> {} [{}]", version, aur::REPO_NAME);
+                    if package.should_ignore() {
+                        print!(" [ignored]");
+                    }
+                } else if let Some(new) = package
+                    .sync_new_version(handle.syncdbs())
+                    .filter(|_| self.upgrade)
+                {
+                    print!(" -> {}", new.version());
 
-                    if package.should_ignore()
-                        || !package
-                            .db()
-                            .unwrap()
-                            .usage()
-                            .unwrap()
-                            .contains(Usage::UPGRADE)
-                    {
+                    let upgradable = new
+                        .db()
+                        .and_then(|db| db.usage().ok())
+                        .map_or(true, |usage| usage.contains(Usage::UPGRADE));
+                    if package.should_ignore() || !upgradable {
                         print!(" [ignored]");
                     }
                 }
@@ -384,6 +542,7 @@ impl Command {
                 println!("{}", package.name());
             }
         }
+        Ok(())
     }
 }
 
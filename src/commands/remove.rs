@@ -1,9 +1,16 @@
-use alpm::{Alpm, PrepareReturn, TransFlag};
+use alpm::{Alpm, Depend, TransFlag};
 use clap::Clap;
-use dialoguer::Confirm;
 use std::cell::RefCell;
 
-use crate::{commands::CommandHandler, config::Config};
+use crate::{
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+    utils::{
+        confirm, glob_match, noyes, orphans, print_summary, print_targets, trans_commit,
+        trans_prepare, transaction,
+    },
+};
 
 /// Remove operations
 #[derive(Clap, Clone)]
@@ -17,9 +24,9 @@ pub struct Command {
     /// Remove packages and their configuration files
     #[clap(short, long, conflicts_with = "print", conflicts_with = "dbonly")]
     pub nosave: bool,
-    /// Remove unnecessary dependencies
-    #[clap(short = 's', long)]
-    pub recurse: bool,
+    /// Remove unnecessary dependencies (-ss includes explicitly installed dependencies)
+    #[clap(short = 's', long, parse(from_occurrences))]
+    pub recurse: i32,
     /// Add a virtual package to satisfy dependencies
     #[clap(long)]
     pub assume_installed: Vec<String>,
@@ -32,50 +39,114 @@ pub struct Command {
     /// print the targets instead of performing the operation
     #[clap(short, long, conflicts_with = "dbonly", conflicts_with = "noscriptlet")]
     pub print: bool,
-    /// Specify how the targets should be printed
-    #[clap(long)]
+    /// Specify how the targets should be printed (%n %v %r %s %l %b %a)
+    #[clap(long, requires = "print")]
     pub print_format: Option<String>,
+    /// Remove all dependencies no explicitly installed package needs (-Rns --orphans)
+    #[clap(long)]
+    pub orphans: bool,
     /// The packages to remove
-    #[clap(required = true)]
+    #[clap(required_unless_present = "orphans")]
     pub packages: Vec<String>,
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, config: Config) {
-        alpm_handle.borrow().trans_init(TransFlag::empty()).unwrap();
-        for pkg in &self.packages {
-            let handle = alpm_handle.borrow();
-            if let Ok(pkg) = handle.localdb().pkg(pkg) {
-                handle.trans_remove_pkg(pkg).unwrap();
-            } else if let Ok(group) = handle.localdb().group(pkg) {
-                for member in group.packages() {
-                    handle.trans_remove_pkg(member).unwrap();
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, config: Config) -> Result<()> {
+        let mut handle = alpm_handle.borrow_mut();
+        for dep in &self.assume_installed {
+            handle.add_assume_installed(&Depend::new(dep.as_str()))?;
+        }
+
+        transaction(&mut handle, self.trans_flags(), |handle| {
+            let mut targets = self.packages.clone();
+            if self.orphans {
+                // Like -Qdt, optional dependencies are kept
+                let mut orphans: Vec<String> = orphans(handle, true)?.into_iter().collect();
+                if orphans.is_empty() && targets.is_empty() {
+                    println!("there are no orphaned packages");
+                    return Ok(());
                 }
+                orphans.sort();
+                targets.extend(orphans);
             }
-        }
-        if let Err(err) = alpm_handle.borrow_mut().trans_prepare() {
-            match err {
-                (err, _) => match err {
-                    PrepareReturn::UnsatisfiedDeps(deps) => {
-                        for dep in deps {
-                            println!(
-                                "removing {} breaks {}",
-                                dep.causing_pkg().unwrap(),
-                                dep.depend()
-                            );
-                        }
+
+            let mut missing = Vec::new();
+            for pkg in &targets {
+                if let Ok(pkg) = handle.localdb().pkg(pkg) {
+                    handle.trans_remove_pkg(pkg)?;
+                } else if let Ok(group) = handle.localdb().group(pkg) {
+                    for member in group.packages() {
+                        handle.trans_remove_pkg(member)?;
                     }
-                    _ => {}
-                },
+                } else {
+                    missing.push(pkg.clone());
+                }
+            }
+            if !missing.is_empty() {
+                return Err(Error::TargetNotFound(missing));
+            }
+            trans_prepare(handle)?;
+            if self.print {
+                print_targets(handle, self.print_format.as_deref());
+                return Ok(());
+            }
+
+            let held: Vec<String> = handle
+                .trans_remove()
+                .map(|package| package.name().to_string())
+                .filter(|name| config.hold_pkg.iter().any(|hold| glob_match(hold, name)))
+                .collect();
+            for name in &held {
+                eprintln!("warning: {} is designated as a HoldPkg.", name);
+            }
+            if !held.is_empty()
+                && !noyes(
+                    "HoldPkg was found in target list. Do you want to continue?",
+                    config.no_confirm,
+                )
+            {
+                return Err(Error::Aborted);
             }
-        }
 
-        if Confirm::new()
-            .with_prompt("Do you want to remove these packages?")
-            .interact()
-            .unwrap()
-        {
-            alpm_handle.borrow_mut().trans_commit().unwrap();
+            print_summary(handle);
+            if !confirm("Do you want to remove these packages?", config.no_confirm) {
+                return Err(Error::Aborted);
+            }
+            trans_commit(handle)
+        })
+    }
+}
+
+impl Command {
+    fn trans_flags(&self) -> TransFlag {
+        let mut flags = TransFlag::empty();
+        if self.nodeps >= 1 {
+            flags |= TransFlag::NO_DEP_VERSION;
+        }
+        if self.nodeps >= 2 {
+            flags |= TransFlag::NO_DEPS;
+        }
+        if self.cascade {
+            flags |= TransFlag::CASCADE;
+        }
+        if self.nosave {
+            flags |= TransFlag::NO_SAVE;
+        }
+        if self.recurse >= 1 {
+            flags |= TransFlag::RECURSE;
+        }
+        if self.recurse >= 2 {
+            flags |= TransFlag::RECURSE_ALL;
+        }
+        if self.dbonly {
+            flags |= TransFlag::DB_ONLY;
+        }
+        if self.noscriptlet {
+            flags |= TransFlag::NO_SCRIPTLET;
+        }
+        if self.print {
+            flags |= TransFlag::NO_LOCK;
         }
+        flags
     }
 }
@@ -1,8 +1,19 @@
-use alpm::Alpm;
+use alpm::{vercmp, Alpm, Depend, Package, TransFlag};
 use clap::Clap;
-use std::{cell::RefCell, path::PathBuf};
+use std::{cell::RefCell, collections::HashMap, fs, path::PathBuf};
 
-use crate::{commands::CommandHandler, config::Config};
+use crate::{
+    aur,
+    commands::{upgrade, CommandHandler},
+    config::Config,
+    download::{download_all, Download},
+    error::{Error, Result},
+    output::{Output, PackageRecord},
+    utils::{
+        confirm, format_size, print_summary, print_targets, refresh_databases, trans_commit,
+        trans_prepare, transaction, EnumFormatter, Join, PrintTarget,
+    },
+};
 
 /// Sync operations
 #[derive(Clap, Clone)]
@@ -21,6 +32,12 @@ pub struct Command {
         conflicts_with = "downloadonly"
     )]
     pub clean: i32,
+    /// Keep the N most recent versions of each package when cleaning the cache
+    #[clap(long, requires = "clean")]
+    pub keep: Option<usize>,
+    /// Only list what would be removed from the cache
+    #[clap(long, requires = "clean")]
+    pub dry_run: bool,
     /// View all members of a groups (-gg to view all groups members)
     #[clap(
         short,
@@ -42,7 +59,7 @@ pub struct Command {
         conflicts_with = "downloadonly"
     )]
     pub info: i32,
-    /// Show all packages in a repo
+    /// Show all packages in the given repos (all repos if none are given)
     #[clap(
         short,
         long,
@@ -50,11 +67,11 @@ pub struct Command {
         conflicts_with = "sysupgrade",
         conflicts_with = "downloadonly"
     )]
-    pub list: Option<String>,
+    pub list: bool,
     /// Show less information for query and search
     #[clap(short, long)]
     pub quiet: bool,
-    /// Search installed packages for matching strings
+    /// Search remote repositories for matching strings
     #[clap(
         short,
         long,
@@ -64,12 +81,29 @@ pub struct Command {
         conflicts_with = "downloadonly"
     )]
     pub search: Option<String>,
+    /// Which fields to match when searching the AUR
+    #[clap(
+        long,
+        requires = "search",
+        possible_values = &["name", "name-desc", "maintainer"],
+        default_value = "name-desc"
+    )]
+    pub search_by: String,
     /// Upgrade installed packages (-uu enables downgrades)
     #[clap(short = 'u', long, parse(from_occurrences))]
     pub sysupgrade: i32,
     /// Download packages but do not install/upgrade anything
     #[clap(short = 'w', long)]
     pub downloadonly: bool,
+    /// Only operate on AUR packages
+    #[clap(long, conflicts_with = "repo")]
+    pub aur: bool,
+    /// Only operate on sync database packages
+    #[clap(long)]
+    pub repo: bool,
+    /// Check development packages for upstream changes during sysupgrade
+    #[clap(long, conflicts_with = "repo")]
+    pub devel: bool,
     /// Download fresh databases from the server (-yy to force a refresh)
     #[clap(short = 'y', long, parse(from_occurrences))]
     pub refresh: i32,
@@ -103,8 +137,8 @@ pub struct Command {
     /// print the targets instead of performing the operation
     #[clap(short, long, conflicts_with = "dbonly", conflicts_with = "noscriptlet")]
     pub print: bool,
-    /// Specify how the targets should be printed
-    #[clap(long)]
+    /// Specify how the targets should be printed (%n %v %r %s %l %b %a)
+    #[clap(long, requires = "print")]
     pub print_format: Option<String>,
     /// The packages to install
     #[clap()]
@@ -112,7 +146,839 @@ pub struct Command {
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, config: Config) {
-        todo!("Impl SYNC!")
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, config: Config) -> Result<()> {
+        {
+            let mut handle = alpm_handle.borrow_mut();
+            for package in &self.ignore {
+                handle.add_ignorepkg(package.as_str())?;
+            }
+            for file in &self.overwrite {
+                handle.add_overwrite_file(file.to_string_lossy().as_ref())?;
+            }
+            for dep in &self.assume_installed {
+                handle.add_assume_installed(&Depend::new(dep.as_str()))?;
+            }
+        }
+
+        if self.clean >= 1 {
+            return self.clean(&alpm_handle.borrow(), &config);
+        }
+
+        if self.refresh >= 1 {
+            println!("Synchronizing package databases...");
+            refresh_databases(&mut alpm_handle.borrow_mut(), self.refresh >= 2)?;
+        }
+
+        if self.list {
+            self.list(&alpm_handle.borrow())
+        } else if self.groups >= 1 {
+            self.groups(&alpm_handle.borrow())
+        } else if let Some(query) = &self.search {
+            self.search(&alpm_handle.borrow(), &config, query)
+        } else if self.info >= 1 {
+            self.info(&alpm_handle.borrow(), &config)
+        } else if self.sysupgrade >= 1 || !self.packages.is_empty() {
+            self.install(alpm_handle, &config)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Command {
+    fn trans_flags(&self) -> TransFlag {
+        let mut flags = TransFlag::empty();
+        if self.needed {
+            flags |= TransFlag::NEEDED;
+        }
+        if self.nodeps >= 1 {
+            flags |= TransFlag::NO_DEP_VERSION;
+        }
+        if self.nodeps >= 2 {
+            flags |= TransFlag::NO_DEPS;
+        }
+        if self.asdeps {
+            flags |= TransFlag::ALL_DEPS;
+        }
+        if self.asexplicit {
+            flags |= TransFlag::ALL_EXPLICIT;
+        }
+        if self.dbonly {
+            flags |= TransFlag::DB_ONLY;
+        }
+        if self.noscriptlet {
+            flags |= TransFlag::NO_SCRIPTLET;
+        }
+        if self.downloadonly {
+            flags |= TransFlag::DOWNLOAD_ONLY;
+        }
+        if self.print {
+            flags |= TransFlag::NO_LOCK;
+        }
+        flags
+    }
+
+    fn clean(&self, handle: &Alpm, config: &Config) -> Result<()> {
+        let mut targets = Vec::new();
+
+        for cache_dir in &config.paths.cache_dirs {
+            let entries = match fs::read_dir(cache_dir) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    eprintln!(
+                        "could not access cache directory {}: {}",
+                        cache_dir.display(),
+                        err
+                    );
+                    continue;
+                }
+            };
+            let mut packages: HashMap<String, Vec<(String, PathBuf)>> = HashMap::new();
+            for entry in entries {
+                let path = entry?.path();
+                let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+                if !file_name.contains(".pkg.tar") || file_name.ends_with(".sig") {
+                    continue;
+                }
+                if let Some((name, version)) = parse_package_file(&file_name) {
+                    packages.entry(name).or_default().push((version, path));
+                }
+            }
+
+            for (name, mut versions) in packages {
+                if self.clean >= 2 {
+                    targets.extend(versions.into_iter().map(|(_, path)| path));
+                } else if let Some(keep) = self.keep {
+                    versions.sort_by(|(a, _), (b, _)| vercmp(b.clone(), a.clone()));
+                    targets.extend(versions.into_iter().skip(keep).map(|(_, path)| path));
+                } else {
+                    let installed = handle
+                        .localdb()
+                        .pkg(name.as_str())
+                        .map(|package| package.version().to_string())
+                        .ok();
+                    targets.extend(
+                        versions
+                            .into_iter()
+                            .filter(|(version, _)| Some(version) != installed.as_ref())
+                            .map(|(_, path)| path),
+                    );
+                }
+            }
+        }
+
+        // --keep only prunes package versions, unused databases are left alone
+        let sync_dir = PathBuf::from(handle.dbpath()).join("sync");
+        let entries = match fs::read_dir(&sync_dir) {
+            Ok(entries) if self.keep.is_none() => entries.collect(),
+            _ => Vec::new(),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(db_name) = parse_db_file(&file_name) {
+                if handle.syncdbs().all(|db| db.name() != db_name) {
+                    targets.push(entry.path());
+                }
+            }
+        }
+
+        let signatures: Vec<PathBuf> = targets
+            .iter()
+            .map(|path| PathBuf::from(format!("{}.sig", path.display())))
+            .filter(|path| path.exists())
+            .collect();
+        targets.extend(signatures);
+        targets.sort();
+        targets.dedup();
+
+        let size: u64 = targets
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        if targets.is_empty() {
+            println!("there is nothing to clean");
+            return Ok(());
+        }
+
+        if self.dry_run {
+            for path in &targets {
+                println!("{}", path.display());
+            }
+            println!();
+            println!(
+                "{} files would be removed, freeing {}",
+                targets.len(),
+                format_size(size as i64)
+            );
+        } else if confirm(
+            &format!("Remove {} files from the cache?", targets.len()),
+            config.no_confirm,
+        ) {
+            let mut freed = 0;
+            let mut failed = 0;
+            for path in &targets {
+                let len = fs::metadata(path).map_or(0, |metadata| metadata.len());
+                match fs::remove_file(path) {
+                    Ok(()) => freed += len,
+                    Err(err) => {
+                        eprintln!("could not remove {}: {}", path.display(), err);
+                        failed += 1;
+                    }
+                }
+            }
+            println!("Freed {}", format_size(freed as i64));
+            if failed > 0 {
+                return Err(Error::Failed(format!("could not remove {} files", failed)));
+            }
+        } else {
+            return Err(Error::Aborted);
+        }
+        Ok(())
+    }
+
+    fn list(&self, handle: &Alpm) -> Result<()> {
+        let missing: Vec<String> = self
+            .packages
+            .iter()
+            .filter(|repo| handle.syncdbs().all(|db| db.name() != repo.as_str()))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::Failed(format!(
+                "repository not found: {}",
+                missing.join(", ")
+            )));
+        }
+
+        for db in handle.syncdbs() {
+            if !self.packages.is_empty() && !self.packages.iter().any(|repo| repo == db.name()) {
+                continue;
+            }
+            for package in db.pkgs()? {
+                if self.quiet {
+                    println!("{}", package.name());
+                    continue;
+                }
+                print!("{} {} {}", db.name(), package.name(), package.version());
+                if let Ok(local) = handle.localdb().pkg(package.name()) {
+                    if local.version() == package.version() {
+                        print!(" [installed]");
+                    } else {
+                        print!(" [installed: {}]", local.version());
+                    }
+                }
+                println!();
+            }
+        }
+        Ok(())
+    }
+
+    fn groups(&self, handle: &Alpm) -> Result<()> {
+        if self.packages.is_empty() {
+            let mut seen = Vec::new();
+            for db in handle.syncdbs() {
+                for group in db.groups()? {
+                    if self.groups >= 2 {
+                        for package in group.packages() {
+                            println!("{} {}", group.name(), package.name());
+                        }
+                    } else if !seen.contains(&group.name()) {
+                        seen.push(group.name());
+                        println!("{}", group.name());
+                    }
+                }
+            }
+        } else {
+            let mut missing = Vec::new();
+            for target in &self.packages {
+                let mut found = false;
+                for group in handle
+                    .syncdbs()
+                    .filter_map(|db| db.group(target.as_str()).ok())
+                {
+                    found = true;
+                    for package in group.packages() {
+                        if self.quiet {
+                            println!("{}", package.name());
+                        } else {
+                            println!("{} {}", group.name(), package.name());
+                        }
+                    }
+                }
+                if !found {
+                    missing.push(target.clone());
+                }
+            }
+            if !missing.is_empty() {
+                return Err(Error::TargetNotFound(missing));
+            }
+        }
+        Ok(())
+    }
+
+    fn search(&self, handle: &Alpm, config: &Config, query: &str) -> Result<()> {
+        let mut output = Output::new(config.output);
+        let mut found = 0;
+        if !self.aur {
+            let query = vec![query.to_string()];
+            for db in handle.syncdbs() {
+                for package in db.search(&query)? {
+                    found += 1;
+                    if !output.is_text() {
+                        let mut record = PackageRecord::from_alpm(&package);
+                        record.installed_version = installed_version(handle, package.name());
+                        output.emit(&record);
+                        continue;
+                    }
+                    if self.quiet {
+                        println!("{}", package.name());
+                        continue;
+                    }
+                    print!("{}/{} {}", db.name(), package.name(), package.version());
+                    let mut groups = package.groups();
+                    if !groups.is_empty() {
+                        print!(" ({})", groups.join(" "));
+                    }
+                    if handle.localdb().pkg(package.name()).is_ok() {
+                        print!(" [installed]");
+                    }
+                    println!();
+                    println!("    {}", package.desc().map_or("None", |desc| desc));
+                }
+            }
+        }
+
+        let mut result = Ok(());
+        if !self.repo {
+            let strategy = aur::search_by(&self.search_by);
+            match aur::search(&aur::handle(&config.aur), query, strategy) {
+                Ok(packages) => {
+                    for package in packages {
+                        if output.is_text() {
+                            aur::print_search(&package, handle, self.quiet);
+                        } else {
+                            let mut record = PackageRecord::from_aur(&package);
+                            record.installed_version = installed_version(handle, &package.name);
+                            output.emit(&record);
+                        }
+                    }
+                }
+                // Repo results were already printed, an offline AUR should not fail them
+                Err(err) if found > 0 => eprintln!("warning: failed to query the AUR: {}", err),
+                Err(err) => result = Err(err.into()),
+            }
+        }
+        output.finish();
+        result
+    }
+
+    fn info(&self, handle: &Alpm, config: &Config) -> Result<()> {
+        let mut output = Output::new(config.output);
+        let mut missing = Vec::new();
+        if self.aur {
+            missing.extend(self.packages.iter().map(String::as_str));
+        } else if self.packages.is_empty() {
+            for db in handle.syncdbs() {
+                for package in db.pkgs()? {
+                    self.print_info(handle, &package, &mut output);
+                }
+            }
+        } else {
+            for target in &self.packages {
+                let packages = resolve_target(handle, target);
+                if packages.is_empty() {
+                    missing.push(target.trim_start_matches("aur/"));
+                }
+                for package in packages {
+                    self.print_info(handle, &package, &mut output);
+                }
+            }
+        }
+
+        let mut result = Ok(());
+        if !missing.is_empty() && !self.repo {
+            match aur::info(&aur::handle(&config.aur), &missing) {
+                Ok(packages) => {
+                    for package in &packages {
+                        if output.is_text() {
+                            aur::print_info(package, &config.aur);
+                        } else {
+                            let mut record = PackageRecord::from_aur(package);
+                            record.installed_version = installed_version(handle, &package.name);
+                            output.emit(&record);
+                        }
+                    }
+                    missing.retain(|target| {
+                        !packages.iter().any(|package| package.name == *target)
+                    });
+                }
+                Err(err) => {
+                    result = Err(err.into());
+                    missing.clear();
+                }
+            }
+        }
+        output.finish();
+        if !missing.is_empty() {
+            result = Err(Error::TargetNotFound(
+                missing.into_iter().map(String::from).collect(),
+            ));
+        }
+        result
+    }
+
+    fn print_info(&self, handle: &Alpm, package: &Package, output: &mut Output) {
+        if !output.is_text() {
+            let mut record = PackageRecord::from_alpm(package);
+            record.installed_version = installed_version(handle, package.name());
+            output.emit(&record);
+            return;
+        }
+        println!(
+            "Repository : {}",
+            package.db().map_or("None", |db| db.name())
+        );
+        println!("Name : {}", package.name());
+        println!("Version : {}", package.version());
+        println!(
+            "Description : {}",
+            package.desc().map_or("None", |desc| desc)
+        );
+        println!("Architecture : {}", package.arch().map_or("None", |a| a));
+        println!("URL : {}", package.url().map_or("None", |p| p));
+        println!("Licenses : {}", package.licenses().join(" "));
+        println!("Groups : {}", package.groups().join(" "));
+        println!("Provides : {}", package.provides().join(" "));
+        println!("Depends On : {}", package.depends().join(" "));
+        println!("Optional Deps : {}", package.optdepends().join("\n"));
+        if self.info >= 2 {
+            println!("Required By : {}", package.required_by().join(" "));
+            println!("Optional For : {}", package.optional_for().join(" "));
+        }
+        println!("Conflicts With : {}", package.conflicts().join(" "));
+        println!("Replaces : {}", package.replaces().join(" "));
+        println!("Download Size : {}", format_size(package.download_size()));
+        println!("Installed Size : {}", format_size(package.isize()));
+        println!("Packager : {}", package.packager().map_or("None", |p| p));
+        println!("Build Date : {}", package.build_date());
+        println!(
+            "Validated By : {}",
+            EnumFormatter::from(package.validation())
+        );
+        if self.info >= 2 {
+            println!(
+                "Backup Files : {}",
+                package.backup().map(|backup| backup.name()).join("\n")
+            );
+        }
+        println!();
+    }
+
+    fn install(&self, alpm_handle: &RefCell<Alpm>, config: &Config) -> Result<()> {
+        let (repo_targets, mut aur_targets) =
+            self.partition_targets(&alpm_handle.borrow(), config)?;
+
+        if !self.aur && (self.sysupgrade >= 1 || !repo_targets.is_empty()) {
+            self.install_repo(alpm_handle, config, &repo_targets)?;
+        }
+
+        let raur = aur::handle(&config.aur);
+        let requested: Vec<String> = aur_targets
+            .iter()
+            .map(|package| package.name.clone())
+            .collect();
+        let mut updates = Vec::new();
+        if self.sysupgrade >= 1 && !self.repo {
+            updates.extend(aur::updates(&raur, &alpm_handle.borrow())?);
+        }
+        if self.sysupgrade >= 1 && self.devel {
+            updates.extend(self.devel_updates(&alpm_handle.borrow(), config, &raur)?);
+        }
+        for update in updates {
+            if aur_targets.iter().any(|target| target.name == update.name) {
+                continue;
+            }
+            // --ignore, IgnorePkg and IgnoreGroup all end up in the handle
+            let local = alpm_handle
+                .borrow()
+                .localdb()
+                .pkg(update.name.as_str())
+                .ok()
+                .map(|local| (local.should_ignore(), local.version().to_string()));
+            match local {
+                Some((true, version)) => eprintln!(
+                    "warning: {}: ignoring package upgrade ({} => {})",
+                    update.name, version, update.version
+                ),
+                _ => aur_targets.push(update),
+            }
+        }
+        if aur_targets.is_empty() {
+            return Ok(());
+        } else if self.downloadonly && !self.print {
+            for package in aur_targets {
+                eprintln!("skipping AUR package {}: it has to be built", package.name);
+            }
+            return Ok(());
+        }
+
+        let plan = aur::Resolver::new(&*alpm_handle.borrow(), &raur)
+            .resolve(aur_targets)
+            .map_err(|err| match err {
+                aur::ResolveError::Rpc(err) => Error::from(err),
+                err => Error::Failed(err.to_string()),
+            })?;
+        if !self.print {
+            plan.print();
+        }
+
+        if !plan.repo.is_empty() {
+            let deps = Command {
+                asdeps: true,
+                asexplicit: false,
+                sysupgrade: 0,
+                ..self.clone()
+            };
+            let targets: Vec<&str> = plan.repo.iter().map(String::as_str).collect();
+            deps.install_repo(alpm_handle, config, &targets)?;
+        }
+
+        // Only packages pulled in by the resolver are dependencies, upgrades keep
+        // the install reason they already have
+        let deps: Vec<&str> = plan
+            .aur
+            .iter()
+            .map(|package| package.name.as_str())
+            .filter(|name| {
+                !requested.iter().any(|target| target == name)
+                    && alpm_handle.borrow().localdb().pkg(*name).is_err()
+            })
+            .collect();
+        self.install_aur(alpm_handle, config, &plan.aur, &deps)
+    }
+
+    /// Split the targets into ones found in the sync databases and ones found in the AUR
+    fn partition_targets(
+        &self,
+        handle: &Alpm,
+        config: &Config,
+    ) -> Result<(Vec<&str>, Vec<raur::Package>)> {
+        let (repo_targets, other): (Vec<&str>, Vec<&str>) = self
+            .packages
+            .iter()
+            .map(String::as_str)
+            .partition(|target| !self.aur && !resolve_target(handle, target).is_empty());
+        if other.is_empty() {
+            return Ok((repo_targets, Vec::new()));
+        } else if self.repo {
+            return Err(Error::TargetNotFound(
+                other.into_iter().map(String::from).collect(),
+            ));
+        }
+
+        let other: Vec<&str> = other
+            .into_iter()
+            .map(|target| target.trim_start_matches("aur/"))
+            .collect();
+        let aur_targets = aur::info(&aur::handle(&config.aur), &other)?;
+
+        let missing: Vec<String> = other
+            .into_iter()
+            .filter(|target| !aur_targets.iter().any(|package| package.name == *target))
+            .map(String::from)
+            .collect();
+        if missing.is_empty() {
+            Ok((repo_targets, aur_targets))
+        } else {
+            Err(Error::TargetNotFound(missing))
+        }
+    }
+
+    fn install_repo(
+        &self,
+        alpm_handle: &RefCell<Alpm>,
+        config: &Config,
+        targets: &[&str],
+    ) -> Result<()> {
+        transaction(&mut alpm_handle.borrow_mut(), self.trans_flags(), |handle| {
+            if self.sysupgrade >= 1 {
+                if !self.print {
+                    println!("Starting full system upgrade...");
+                }
+                // libalpm only considers databases with `Usage::UPGRADE` here
+                handle.sync_sysupgrade(self.sysupgrade >= 2)?;
+            }
+
+            for target in targets {
+                for package in resolve_target(handle, target) {
+                    let name = package.name().to_string();
+                    if let Err(err) = handle.trans_add_pkg(package) {
+                        eprintln!("skipping target {}: {}", name, err);
+                    }
+                }
+            }
+            trans_prepare(handle)?;
+
+            if self.print {
+                print_targets(handle, self.print_format.as_deref());
+                return Ok(());
+            }
+            if handle.trans_add().is_empty() && handle.trans_remove().is_empty() {
+                println!("there is nothing to do");
+                return Ok(());
+            }
+
+            print_summary(handle);
+
+            let prompt = if self.downloadonly {
+                "Proceed with download?"
+            } else {
+                "Proceed with installation?"
+            };
+            if !confirm(prompt, config.no_confirm) {
+                return Err(Error::Aborted);
+            }
+            if config.parallel_downloads > 1 {
+                prefetch(handle, config);
+            }
+            trans_commit(handle)
+        })
+    }
+
+    /// Installed devel packages whose upstream changed since they were built
+    fn devel_updates(
+        &self,
+        handle: &Alpm,
+        config: &Config,
+        raur: &raur::Handle,
+    ) -> Result<Vec<raur::Package>> {
+        let state = aur::DevelState::load(&aur::DevelState::path(&config.paths.database))?;
+        let outdated = state.outdated();
+        let names: Vec<&str> = handle
+            .localdb()
+            .pkgs()?
+            .filter(|package| {
+                package
+                    .base()
+                    .map_or(false, |base| outdated.contains(&base))
+            })
+            .map(|package| package.name())
+            .collect();
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(aur::info(raur, &names)?)
+    }
+
+    /// Remember the revisions a devel package base was built from
+    fn record_devel(&self, builder: &aur::Builder, config: &Config, base: &str) {
+        let path = aur::DevelState::path(&config.paths.database);
+        let result = aur::DevelState::load(&path).and_then(|mut state| {
+            let srcdir = builder.dir(base).join("src");
+            let sources = aur::VcsSource::from_srcinfo(&builder.srcinfo(base)?, &srcdir)?;
+            state.record(base, sources);
+            state.save(&path)
+        });
+        if let Err(err) = result {
+            eprintln!("failed to record the upstream revision of {}: {}", base, err);
+        }
+    }
+
+    /// Build and install `packages` one package base at a time, in order
+    fn install_aur(
+        &self,
+        alpm_handle: &RefCell<Alpm>,
+        config: &Config,
+        packages: &[raur::Package],
+        deps: &[&str],
+    ) -> Result<()> {
+        if self.print {
+            let format = self.print_format.as_deref().unwrap_or("%l");
+            for package in packages {
+                let target = PrintTarget {
+                    name: &package.name,
+                    version: &package.version,
+                    repo: aur::REPO_NAME,
+                    size: 0,
+                    location: format!(
+                        "{}/cgit/aur.git/snapshot/{}.tar.gz",
+                        config.aur.base_url(),
+                        package.package_base
+                    ),
+                    base: &package.package_base,
+                    arch: "",
+                };
+                println!("{}", target.format(format));
+            }
+            return Ok(());
+        }
+
+        let builder = aur::Builder::new(&config.aur).map_err(|err| {
+            Error::Failed(format!("failed to set up the AUR build directory: {}", err))
+        })?;
+        let mut bases: Vec<&str> = Vec::new();
+        for package in packages {
+            if !bases.contains(&package.package_base.as_str()) {
+                bases.push(package.package_base.as_str());
+            }
+        }
+
+        for base in &bases {
+            let accepted = builder
+                .review(base, config.no_confirm)
+                .map_err(|err| Error::Network(format!("failed to retrieve {}: {}", base, err)))?;
+            if !accepted {
+                return Err(Error::Aborted);
+            }
+        }
+
+        for base in &bases {
+            let base_packages: Vec<&raur::Package> = packages
+                .iter()
+                .filter(|package| package.package_base == *base)
+                .collect();
+            let is_dep = base_packages
+                .iter()
+                .all(|package| deps.contains(&package.name.as_str()));
+            let is_target = base_packages.iter().any(|package| {
+                self.packages
+                    .iter()
+                    .any(|target| target.trim_start_matches("aur/") == package.name)
+            });
+
+            let files = builder
+                .build(base)
+                .map_err(|err| Error::Failed(format!("failed to build {}: {}", base, err)))?
+                .iter()
+                .filter(|path| {
+                    aur::package_name(path).map_or(false, |name| {
+                        base_packages.iter().any(|package| package.name == name)
+                    })
+                })
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+
+            upgrade::Command {
+                needed: self.needed,
+                nodeps: self.nodeps,
+                overwrite: self.overwrite.clone(),
+                asdeps: is_dep || (self.asdeps && is_target),
+                asexplicit: self.asexplicit && is_target,
+                ignore: self.ignore.clone(),
+                assume_installed: self.assume_installed.clone(),
+                dbonly: self.dbonly,
+                noscriptlet: self.noscriptlet,
+                print: self.print,
+                print_format: self.print_format.clone(),
+                files,
+            }
+            .install(alpm_handle, config)?;
+            if base_packages
+                .iter()
+                .any(|package| aur::is_devel(&package.name))
+            {
+                self.record_devel(&builder, config, base);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Download the transaction packages concurrently before libalpm gets to them,
+/// anything that fails here is left for libalpm to retry
+fn prefetch(handle: &Alpm, config: &Config) {
+    let cache_dir = match config.paths.cache_dirs.first() {
+        Some(cache_dir) => cache_dir,
+        None => return,
+    };
+    let downloads: Vec<Download> = handle
+        .trans_add()
+        .filter(|package| {
+            !config
+                .paths
+                .cache_dirs
+                .iter()
+                .any(|dir| dir.join(package.filename()).exists())
+        })
+        .filter_map(|package| {
+            Some(Download {
+                filename: package.filename().to_string(),
+                servers: package.db()?.servers().map(String::from).collect(),
+                size: package.size(),
+                sha256: package.sha256sum().map(String::from),
+            })
+        })
+        .collect();
+    if downloads.is_empty() {
+        return;
+    }
+
+    println!("Retrieving packages...");
+    let failed = download_all(
+        downloads,
+        cache_dir,
+        config.parallel_downloads,
+        config.download_timeout,
+    );
+    for (filename, err) in failed {
+        eprintln!("failed to retrieve {}: {}", filename, err);
+    }
+}
+
+fn installed_version(handle: &Alpm, name: &str) -> Option<String> {
+    handle
+        .localdb()
+        .pkg(name)
+        .ok()
+        .map(|package| package.version().to_string())
+}
+
+/// Split a package file name (`name-pkgver-pkgrel-arch.pkg.tar.*`) into name and version
+fn parse_package_file(file_name: &str) -> Option<(String, String)> {
+    let mut split = file_name.rsplitn(4, '-');
+    let _arch = split.next()?;
+    let pkgrel = split.next()?;
+    let pkgver = split.next()?;
+    let name = split.next()?;
+    Some((name.to_string(), format!("{}-{}", pkgver, pkgrel)))
+}
+
+/// The repository of a sync database file (`repo.db`, `repo.files` or their `.sig`)
+fn parse_db_file(file_name: &str) -> Option<&str> {
+    let file_name = file_name.strip_suffix(".sig").unwrap_or(file_name);
+    file_name
+        .strip_suffix(".db")
+        .or_else(|| file_name.strip_suffix(".files"))
+}
+
+/// Resolve a target (`name`, `repo/name`, a group or a provision) against the sync databases
+fn resolve_target<'a>(handle: &'a Alpm, target: &str) -> Vec<Package<'a>> {
+    let mut split = target.splitn(2, '/');
+    let (repo, name) = match (split.next(), split.next()) {
+        (Some(repo), Some(name)) => (Some(repo), name),
+        _ => (None, target),
+    };
+    let dbs = || {
+        handle
+            .syncdbs()
+            .filter(move |db| repo.map_or(true, |repo| db.name() == repo))
+    };
+
+    if let Some(package) = dbs().find_map(|db| db.pkg(name).ok()) {
+        vec![package]
+    } else if let Some(group) = dbs().find_map(|db| db.group(name).ok()) {
+        group.packages().collect()
+    } else if repo.is_none() {
+        handle
+            .find_dbs_satisfier(handle.syncdbs(), name)
+            .into_iter()
+            .collect()
+    } else {
+        Vec::new()
     }
 }
@@ -1,8 +1,13 @@
-use alpm::Alpm;
+use alpm::{Alpm, Depend, TransFlag};
 use clap::Clap;
 use std::{cell::RefCell, path::PathBuf};
 
-use crate::{commands::CommandHandler, config::Config};
+use crate::{
+    commands::CommandHandler,
+    config::Config,
+    error::{Error, Result},
+    utils::{confirm, print_summary, print_targets, trans_commit, trans_prepare, transaction},
+};
 
 /// Upgrade operations
 #[derive(Clap, Clone)]
@@ -37,8 +42,8 @@ pub struct Command {
     /// print the targets instead of performing the operation
     #[clap(short, long, conflicts_with = "dbonly", conflicts_with = "noscriptlet")]
     pub print: bool,
-    /// Specify how the targets should be printed
-    #[clap(long)]
+    /// Specify how the targets should be printed (%n %v %r %s %l %b %a)
+    #[clap(long, requires = "print")]
     pub print_format: Option<String>,
     /// The files to upgrade
     #[clap(required = true)]
@@ -46,7 +51,95 @@ pub struct Command {
 }
 
 impl CommandHandler for Command {
-    fn handle(&self, alpm_handle: RefCell<Alpm>, config: Config) {
-        todo!("Impl UPGRADE!")
+    fn handle(&self, alpm_handle: &RefCell<Alpm>, config: Config) -> Result<()> {
+        {
+            let mut handle = alpm_handle.borrow_mut();
+            for package in &self.ignore {
+                handle.add_ignorepkg(package.as_str())?;
+            }
+            for file in &self.overwrite {
+                handle.add_overwrite_file(file.to_string_lossy().as_ref())?;
+            }
+            for dep in &self.assume_installed {
+                handle.add_assume_installed(&Depend::new(dep.as_str()))?;
+            }
+        }
+
+        self.install(alpm_handle, &config)
+    }
+}
+
+impl Command {
+    /// Install `files`, assuming the handle options were already applied
+    pub fn install(&self, alpm_handle: &RefCell<Alpm>, config: &Config) -> Result<()> {
+        let mut handle = alpm_handle.borrow_mut();
+        let mut paths = Vec::new();
+        for file in &self.files {
+            if file.starts_with("http://") || file.starts_with("https://") {
+                let path = handle.fetch_pkgurl(file.as_str()).map_err(|err| {
+                    Error::Network(format!("failed to retrieve '{}': {}", file, err))
+                })?;
+                paths.push(path);
+            } else {
+                paths.push(file.trim_start_matches("file://").to_string());
+            }
+        }
+
+        transaction(&mut handle, self.trans_flags(), |handle| {
+            let siglevel = handle.local_file_siglevel();
+            for path in &paths {
+                let package = handle.pkg_load(path.as_str(), true, siglevel).map_err(|err| {
+                    Error::Failed(format!("could not load '{}': {}", path, err))
+                })?;
+                if let Err(err) = handle.trans_add_pkg(package) {
+                    eprintln!("skipping target {}: {}", path, err);
+                }
+            }
+
+            trans_prepare(handle)?;
+            if self.print {
+                print_targets(handle, self.print_format.as_deref());
+                return Ok(());
+            }
+            if handle.trans_add().is_empty() && handle.trans_remove().is_empty() {
+                println!("there is nothing to do");
+                return Ok(());
+            }
+
+            print_summary(handle);
+            if !confirm("Proceed with installation?", config.no_confirm) {
+                return Err(Error::Aborted);
+            }
+            trans_commit(handle)
+        })
+    }
+
+    fn trans_flags(&self) -> TransFlag {
+        let mut flags = TransFlag::empty();
+        if self.needed {
+            flags |= TransFlag::NEEDED;
+        }
+        if self.nodeps >= 1 {
+            flags |= TransFlag::NO_DEP_VERSION;
+        }
+        if self.nodeps >= 2 {
+            flags |= TransFlag::NO_DEPS;
+        }
+        if self.asdeps {
+            flags |= TransFlag::ALL_DEPS;
+        }
+        if self.asexplicit {
+            flags |= TransFlag::ALL_EXPLICIT;
+        }
+        if self.dbonly {
+            flags |= TransFlag::DB_ONLY;
+        }
+        if self.noscriptlet {
+            flags |= TransFlag::NO_SCRIPTLET;
+        }
+        if self.print {
+            flags |= TransFlag::NO_LOCK;
+        }
+        flags
     }
 }
@@ -1,14 +1,18 @@
+use alpm::{SigLevel, Usage};
 use serde::{
     de::{self, MapAccess, Visitor},
-    Deserialize, Deserializer,
+    Deserialize, Deserializer, Serialize,
 };
 use std::{
-    fmt,
+    collections::HashMap,
+    env, fmt,
     fs::File,
     io::{self, BufRead, BufReader},
     path::PathBuf,
 };
 
+use crate::{args::Args, output::OutputFormat};
+
 mod default_computer {
     pub fn verbose() -> i32 {
         0
@@ -28,9 +32,15 @@ mod default_computer {
     pub fn arch() -> String {
         env!("ARCH").to_string()
     }
+    pub fn parallel_downloads() -> usize {
+        1
+    }
+    pub fn siglevel() -> Vec<String> {
+        vec!["Required".to_string(), "DatabaseOptional".to_string()]
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Config {
     #[serde(default = "default_computer::verbose")]
     pub verbose: i32,
@@ -44,13 +54,267 @@ pub struct Config {
     pub download_timeout: bool,
     #[serde(default = "default_computer::arch")]
     pub arch: String,
+    #[serde(default = "default_computer::parallel_downloads")]
+    pub parallel_downloads: usize,
+    #[serde(
+        default = "default_computer::siglevel",
+        deserialize_with = "deserialize_siglevel"
+    )]
+    pub siglevel: Vec<String>,
+    #[serde(default)]
+    pub ignore_pkg: Vec<String>,
+    #[serde(default)]
+    pub ignore_group: Vec<String>,
+    #[serde(default)]
+    pub hold_pkg: Vec<String>,
+    #[serde(default)]
+    pub no_extract: Vec<String>,
+    #[serde(default)]
+    pub no_upgrade: Vec<String>,
     #[serde(default)]
     pub paths: PathConfig,
     #[serde(default)]
     pub databases: Vec<Database>,
+    #[serde(default)]
+    pub aur: AurConfig,
+    #[serde(skip)]
+    pub output: OutputFormat,
+    #[serde(skip)]
+    pub sources: HashMap<&'static str, Source>,
+}
+
+/// Where the effective value of a setting came from
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Source {
+    Default,
+    ConfigFile,
+    Environment,
+    CommandLine,
 }
 
-#[derive(Deserialize)]
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::Default => "default",
+            Source::ConfigFile => "config file",
+            Source::Environment => "environment",
+            Source::CommandLine => "command line",
+        })
+    }
+}
+
+/// Settings that can be overridden, with their key in the config file
+const SETTINGS: &[(&str, &[&str])] = &[
+    ("root", &["paths", "root"]),
+    ("dbpath", &["paths", "database"]),
+    ("cachedir", &["paths", "cache_dirs"]),
+    ("hookdir", &["paths", "hook_dirs"]),
+    ("gpgdir", &["paths", "gpg"]),
+    ("logfile", &["paths", "logfile"]),
+    ("arch", &["arch"]),
+    ("verbose", &["verbose"]),
+    ("noconfirm", &["no_confirm"]),
+    ("disable_download_timeout", &["download_timeout"]),
+];
+
+impl Config {
+    pub fn from_toml(data: &str) -> Result<Config, toml::de::Error> {
+        // Deserializing from a `toml::Value` would lose the position of errors
+        let mut config: Config = toml::from_str(data)?;
+        let value: toml::Value = toml::from_str(data)?;
+        for (name, keys) in SETTINGS {
+            let found = keys
+                .iter()
+                .try_fold(&value, |value, key| value.get(key))
+                .is_some();
+            if found {
+                config.sources.insert(*name, Source::ConfigFile);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn source(&self, name: &str) -> Source {
+        self.sources.get(name).copied().unwrap_or(Source::Default)
+    }
+
+    /// Override settings from `RPAC_<SETTING>` environment variables
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        for (name, _) in SETTINGS {
+            let var = format!("RPAC_{}", name.to_uppercase());
+            if let Some(value) = env::var_os(&var) {
+                let value = value.to_string_lossy();
+                self.set(name, &value)
+                    .map_err(|err| format!("invalid value for {}: {}", var, err))?;
+                self.sources.insert(*name, Source::Environment);
+            }
+        }
+        self.derive_root_paths();
+        Ok(())
+    }
+
+    /// Override settings from the global command line options
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(root) = &args.root {
+            self.paths.root = root.clone();
+            self.sources.insert("root", Source::CommandLine);
+        }
+        if let Some(dbpath) = &args.dbpath {
+            self.paths.database = dbpath.clone();
+            self.sources.insert("dbpath", Source::CommandLine);
+        }
+        if let Some(cachedir) = &args.cachedir {
+            self.paths.cache_dirs = vec![cachedir.clone()];
+            self.sources.insert("cachedir", Source::CommandLine);
+        }
+        if let Some(hookdir) = &args.hookdir {
+            self.paths.hook_dirs = vec![hookdir.clone()];
+            self.sources.insert("hookdir", Source::CommandLine);
+        }
+        if let Some(gpgdir) = &args.gpgdir {
+            self.paths.gpg = gpgdir.clone();
+            self.sources.insert("gpgdir", Source::CommandLine);
+        }
+        if let Some(logfile) = &args.logfile {
+            self.paths.logfile = logfile.clone();
+            self.sources.insert("logfile", Source::CommandLine);
+        }
+        if let Some(arch) = &args.arch {
+            self.arch = arch.clone();
+            self.sources.insert("arch", Source::CommandLine);
+        }
+        if args.verbose > 0 {
+            self.verbose = args.verbose;
+            self.sources.insert("verbose", Source::CommandLine);
+        }
+        if args.noconfirm || args.confirm {
+            self.no_confirm = args.noconfirm;
+            self.sources.insert("noconfirm", Source::CommandLine);
+        }
+        self.output = args.output;
+        if args.disable_download_timeout {
+            self.download_timeout = false;
+            self.sources
+                .insert("disable_download_timeout", Source::CommandLine);
+        }
+        self.derive_root_paths();
+    }
+
+    /// Like pacman, a custom root moves the database and log file along with it
+    /// unless those were set explicitly
+    fn derive_root_paths(&mut self) {
+        if self.source("root") == Source::Default {
+            return;
+        }
+        if self.source("dbpath") == Source::Default {
+            self.paths.database = self.paths.root.join("var/lib/pacman");
+        }
+        if self.source("logfile") == Source::Default {
+            self.paths.logfile = self.paths.root.join("var/log/rpac.log");
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parse_bool = |value: &str| match value {
+            "1" | "true" | "yes" => Ok(true),
+            "0" | "false" | "no" => Ok(false),
+            _ => Err(format!("expected a boolean, got '{}'", value)),
+        };
+        match name {
+            "root" => self.paths.root = PathBuf::from(value),
+            "dbpath" => self.paths.database = PathBuf::from(value),
+            "cachedir" => self.paths.cache_dirs = env::split_paths(value).collect(),
+            "hookdir" => self.paths.hook_dirs = env::split_paths(value).collect(),
+            "gpgdir" => self.paths.gpg = PathBuf::from(value),
+            "logfile" => self.paths.logfile = PathBuf::from(value),
+            "arch" => self.arch = value.to_string(),
+            "verbose" => {
+                self.verbose = value
+                    .parse()
+                    .map_err(|_| format!("expected a number, got '{}'", value))?
+            }
+            "noconfirm" => self.no_confirm = parse_bool(value)?,
+            "disable_download_timeout" => self.download_timeout = !parse_bool(value)?,
+            _ => unreachable!("unknown setting {}", name),
+        }
+        Ok(())
+    }
+
+    fn value(&self, name: &str) -> String {
+        let paths = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        match name {
+            "root" => self.paths.root.display().to_string(),
+            "dbpath" => self.paths.database.display().to_string(),
+            "cachedir" => paths(&self.paths.cache_dirs),
+            "hookdir" => paths(&self.paths.hook_dirs),
+            "gpgdir" => self.paths.gpg.display().to_string(),
+            "logfile" => self.paths.logfile.display().to_string(),
+            "arch" => self.arch.clone(),
+            "verbose" => self.verbose.to_string(),
+            "noconfirm" => self.no_confirm.to_string(),
+            "disable_download_timeout" => (!self.download_timeout).to_string(),
+            _ => unreachable!("unknown setting {}", name),
+        }
+    }
+
+    /// Print the effective value of every overridable setting and its source
+    pub fn print_debug(&self) {
+        for (name, _) in SETTINGS {
+            println!(
+                "{:<25} = {} ({})",
+                name,
+                self.value(name),
+                self.source(name)
+            );
+        }
+    }
+
+    /// The default SigLevel every database inherits
+    pub fn siglevel(&self) -> SigLevel {
+        // Validated when the config is loaded
+        apply_siglevel(SigLevel::empty(), &self.siglevel).unwrap()
+    }
+
+    pub fn database_siglevel(&self, database: &Database) -> SigLevel {
+        match &database.siglevel {
+            Some(siglevel) => apply_siglevel(self.siglevel(), siglevel).unwrap(),
+            None => self.siglevel(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            verbose: default_computer::verbose(),
+            no_confirm: default_computer::no_confirm(),
+            syslog: default_computer::syslog(),
+            color: default_computer::color(),
+            download_timeout: default_computer::download_timeout(),
+            arch: default_computer::arch(),
+            parallel_downloads: default_computer::parallel_downloads(),
+            siglevel: default_computer::siglevel(),
+            ignore_pkg: Vec::new(),
+            ignore_group: Vec::new(),
+            hold_pkg: Vec::new(),
+            no_extract: Vec::new(),
+            no_upgrade: Vec::new(),
+            paths: PathConfig::default(),
+            databases: Vec::new(),
+            aur: AurConfig::default(),
+            output: OutputFormat::default(),
+            sources: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct PathConfig {
     pub root: PathBuf,
     pub database: PathBuf,
@@ -73,52 +337,83 @@ impl Default for PathConfig {
     }
 }
 
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct AurConfig {
+    pub url: String,
+    pub build_dir: PathBuf,
+}
+
+impl AurConfig {
+    /// The AUR web root, derived from the RPC url
+    pub fn base_url(&self) -> &str {
+        self.url.trim_end_matches('/').trim_end_matches("/rpc")
+    }
+}
+
+impl Default for AurConfig {
+    fn default() -> Self {
+        Self {
+            url: "https://aur.archlinux.org/rpc/".to_string(),
+            build_dir: PathBuf::from("~/.cache/rpac/aur"),
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct Database {
     pub name: String,
     pub servers: Vec<String>,
-    pub usage: u32,
-    pub siglevel: u32,
-    pub siglevel_mask: u32,
+    pub usage: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub siglevel: Option<Vec<String>>,
 }
 
 impl Database {
     pub fn new(
         name: String,
         servers: Vec<String>,
-        usage: u32,
-        siglevel: u32,
-        siglevel_mask: u32,
-    ) -> Database {
-        Self {
-            name,
-            servers: {
-                let mut ret = Vec::new();
-                for server in servers {
-                    match File::open(PathBuf::from(server.to_owned())) {
-                        // Parse Mirrorlist
-                        Ok(file) => {
-                            let bufread = BufReader::new(file);
-                            for line in bufread.lines() {
-                                let line = line.unwrap();
-                                let server = line.trim_start_matches("Server = ");
-                                if server.is_empty() || server.starts_with('#') {
-                                    continue;
-                                }
-                                ret.push(server.to_string());
-                            }
+        usage: Vec<String>,
+        siglevel: Option<Vec<String>>,
+    ) -> io::Result<Database> {
+        let mut ret = Vec::new();
+        for server in servers {
+            let mirrorlist_error = |err: io::Error| {
+                io::Error::new(
+                    err.kind(),
+                    format!("could not read mirrorlist {}: {}", server, err),
+                )
+            };
+            match File::open(PathBuf::from(server.to_owned())) {
+                // Parse Mirrorlist
+                Ok(file) => {
+                    let bufread = BufReader::new(file);
+                    for line in bufread.lines() {
+                        let line = line.map_err(mirrorlist_error)?;
+                        let server = line.trim_start_matches("Server = ");
+                        if server.is_empty() || server.starts_with('#') {
+                            continue;
                         }
-                        // File was not found. Probably isn't a mirrorlist, adding it directly
-                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => ret.push(server),
-                        // A error that wasn't expected
-                        Err(e) => panic!("Could not open file: {}", e),
+                        ret.push(server.to_string());
                     }
                 }
-                ret
-            },
+                // File was not found. Probably isn't a mirrorlist, adding it directly
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => ret.push(server),
+                // A error that wasn't expected
+                Err(e) => return Err(mirrorlist_error(e)),
+            }
+        }
+        Ok(Self {
+            name,
+            servers: ret,
             usage,
             siglevel,
-            siglevel_mask,
-        }
+        })
+    }
+
+    pub fn usage(&self) -> Usage {
+        // Validated when the config is loaded
+        parse_usage(&self.usage).unwrap()
     }
 }
 
@@ -132,7 +427,6 @@ impl<'de> Deserialize<'de> for Database {
             Servers,
             Usage,
             SigLevel,
-            SigLevelMask,
         };
 
         impl<'de> Deserialize<'de> for Field {
@@ -146,8 +440,7 @@ impl<'de> Deserialize<'de> for Database {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter
-                            .write_str("`name`, `servers`, `usage`, `siglevel`, `siglevel_mask`")
+                        formatter.write_str("`name`, `servers`, `usage`, `siglevel`")
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -159,7 +452,6 @@ impl<'de> Deserialize<'de> for Database {
                             "servers" => Ok(Field::Servers),
                             "usage" => Ok(Field::Usage),
                             "siglevel" => Ok(Field::SigLevel),
-                            "siglevel_mask" => Ok(Field::SigLevelMask),
 
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
@@ -183,11 +475,10 @@ impl<'de> Deserialize<'de> for Database {
             where
                 V: MapAccess<'de>,
             {
-                let mut name = None;
+                let mut name: Option<String> = None;
                 let mut servers = None;
-                let mut usage = None;
-                let mut siglevel = None;
-                let mut siglevel_mask = None;
+                let mut usage: Option<Vec<String>> = None;
+                let mut siglevel: Option<Vec<String>> = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -215,26 +506,150 @@ impl<'de> Deserialize<'de> for Database {
                             }
                             siglevel = Some(map.next_value()?);
                         }
-                        Field::SigLevelMask => {
-                            if siglevel_mask.is_some() {
-                                return Err(de::Error::duplicate_field("siglevel_mask"));
-                            }
-                            siglevel_mask = Some(map.next_value()?);
-                        }
                     }
                 }
-                let name = name.ok_or_else(|| de::Error::missing_field("secs"))?;
+                let name = name.ok_or_else(|| de::Error::missing_field("name"))?;
                 let servers = servers.ok_or_else(|| de::Error::missing_field("servers"))?;
-                let usage = usage.ok_or_else(|| de::Error::missing_field("usage"))?;
-                let siglevel = siglevel.ok_or_else(|| de::Error::missing_field("siglevel"))?;
-                let siglevel_mask =
-                    siglevel_mask.ok_or_else(|| de::Error::missing_field("siglevel_mask"))?;
+                let usage = usage.unwrap_or_else(|| vec!["All".to_string()]);
+
+                parse_usage(&usage).map_err(|err| {
+                    de::Error::custom(format!("invalid usage of database '{}': {}", name, err))
+                })?;
+                if let Some(siglevel) = &siglevel {
+                    validate_siglevel(siglevel).map_err(|err| {
+                        de::Error::custom(format!(
+                            "invalid siglevel of database '{}': {}",
+                            name, err
+                        ))
+                    })?;
+                }
 
-                Ok(Database::new(name, servers, usage, siglevel, siglevel_mask))
+                Database::new(name, servers, usage, siglevel).map_err(de::Error::custom)
             }
         }
 
-        const FIELDS: &[&str] = &["name", "servers", "usage", "siglevel", "siglevel_mask"];
-        deserializer.deserialize_struct("Duration", FIELDS, DatabaseVisitor)
+        const FIELDS: &[&str] = &["name", "servers", "usage", "siglevel"];
+        deserializer.deserialize_struct("Database", FIELDS, DatabaseVisitor)
+    }
+}
+
+fn deserialize_siglevel<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let siglevel = Vec::<String>::deserialize(deserializer)?;
+    validate_siglevel(&siglevel)
+        .map_err(|err| de::Error::custom(format!("invalid siglevel: {}", err)))?;
+    Ok(siglevel)
+}
+
+pub fn validate_siglevel(values: &[String]) -> Result<(), String> {
+    apply_siglevel(SigLevel::empty(), values).map(|_| ())
+}
+
+/// Apply pacman style SigLevel values like `Required` or `DatabaseOptional` on top of `level`
+pub fn apply_siglevel(mut level: SigLevel, values: &[String]) -> Result<SigLevel, String> {
+    for value in values {
+        let (package, database, option) = if value.starts_with("Package") {
+            (true, false, &value["Package".len()..])
+        } else if value.starts_with("Database") {
+            (false, true, &value["Database".len()..])
+        } else {
+            (true, true, value.as_str())
+        };
+        let bits = |package_bits: SigLevel, database_bits: SigLevel| {
+            let mut bits = SigLevel::empty();
+            if package {
+                bits |= package_bits;
+            }
+            if database {
+                bits |= database_bits;
+            }
+            bits
+        };
+
+        let (set, clear) = match option {
+            "Never" => (
+                SigLevel::empty(),
+                bits(SigLevel::PACKAGE, SigLevel::DATABASE),
+            ),
+            "Optional" => (
+                bits(
+                    SigLevel::PACKAGE | SigLevel::PACKAGE_OPTIONAL,
+                    SigLevel::DATABASE | SigLevel::DATABASE_OPTIONAL,
+                ),
+                SigLevel::empty(),
+            ),
+            "Required" => (
+                bits(SigLevel::PACKAGE, SigLevel::DATABASE),
+                bits(SigLevel::PACKAGE_OPTIONAL, SigLevel::DATABASE_OPTIONAL),
+            ),
+            "TrustedOnly" => (
+                SigLevel::empty(),
+                bits(
+                    SigLevel::PACKAGE_MARGINAL_OK | SigLevel::PACKAGE_UNKNOWN_OK,
+                    SigLevel::DATABASE_MARGINAL_OK | SigLevel::DATABASE_UNKNOWN_OK,
+                ),
+            ),
+            "TrustAll" => (
+                bits(
+                    SigLevel::PACKAGE_MARGINAL_OK | SigLevel::PACKAGE_UNKNOWN_OK,
+                    SigLevel::DATABASE_MARGINAL_OK | SigLevel::DATABASE_UNKNOWN_OK,
+                ),
+                SigLevel::empty(),
+            ),
+            _ => {
+                return Err(format!(
+                    "unknown value '{}', expected one of Never, Optional, Required, \
+                     TrustedOnly or TrustAll, optionally prefixed with Package or Database",
+                    value
+                ))
+            }
+        };
+        level = (level & !clear) | set;
+    }
+    Ok(level)
+}
+
+pub fn parse_usage(values: &[String]) -> Result<Usage, String> {
+    let mut usage = Usage::empty();
+    for value in values {
+        usage |= match value.as_str() {
+            "Sync" => Usage::SYNC,
+            "Search" => Usage::SEARCH,
+            "Install" => Usage::INSTALL,
+            "Upgrade" => Usage::UPGRADE,
+            "All" => Usage::ALL,
+            _ => {
+                return Err(format!(
+                    "unknown value '{}', expected one of Sync, Search, Install, Upgrade or All",
+                    value
+                ))
+            }
+        };
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = Config::from_toml("arch = \"x86_64\"\nverbose = \"loud\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line_col().map(|(line, _)| line + 1), Some(2));
+        assert!(err.to_string().contains("for key `verbose` at line 2"));
+    }
+
+    #[test]
+    fn sources_record_configured_settings() {
+        let config = Config::from_toml("arch = \"aarch64\"\nverbose = 2\n").unwrap();
+        assert_eq!(config.arch, "aarch64");
+        assert_eq!(config.source("arch"), Source::ConfigFile);
+        assert_eq!(config.source("verbose"), Source::ConfigFile);
+        assert_eq!(config.source("dbpath"), Source::Default);
     }
 }
@@ -0,0 +1,287 @@
+use reqwest::blocking::Client;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tempfile::NamedTempFile;
+
+/// How long a mirror may take to accept a connection, the same as libalpm
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A package file and the mirrors it can be fetched from
+pub struct Download {
+    pub filename: String,
+    pub servers: Vec<String>,
+    pub size: i64,
+    pub sha256: Option<String>,
+}
+
+/// Fetch `downloads` into `dest` using up to `jobs` concurrent workers.
+/// Returns the downloads that failed on every mirror together with the last error.
+pub fn download_all(
+    downloads: Vec<Download>,
+    dest: &Path,
+    jobs: usize,
+    timeout: bool,
+) -> Vec<(String, io::Error)> {
+    // Large packages on slow mirrors take as long as they take, only connecting is limited
+    let mut client = Client::builder().timeout(None);
+    if timeout {
+        client = client.connect_timeout(CONNECT_TIMEOUT);
+    }
+    let client = match client.build() {
+        Ok(client) => client,
+        Err(err) => {
+            return downloads
+                .into_iter()
+                .map(|download| {
+                    let err = io::Error::new(ErrorKind::Other, err.to_string());
+                    (download.filename, err)
+                })
+                .collect()
+        }
+    };
+
+    let queue = Arc::new(Mutex::new(downloads));
+    let failed = Arc::new(Mutex::new(Vec::new()));
+
+    let workers: Vec<_> = (0..jobs.max(1))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let failed = Arc::clone(&failed);
+            let dest = dest.to_path_buf();
+            let client = client.clone();
+            thread::spawn(move || loop {
+                let download = match queue.lock().unwrap().pop() {
+                    Some(download) => download,
+                    None => break,
+                };
+                match download.fetch(&client, &dest) {
+                    Ok(path) => println!("  Downloaded {}", path.display()),
+                    Err(err) => failed.lock().unwrap().push((download.filename, err)),
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    Arc::try_unwrap(failed)
+        .map(|failed| failed.into_inner().unwrap())
+        .unwrap_or_default()
+}
+
+impl Download {
+    /// Try each mirror in order until one serves a file with the expected size and checksum
+    fn fetch(&self, client: &Client, dest: &Path) -> io::Result<PathBuf> {
+        let path = dest.join(&self.filename);
+        let mut last_err = io::Error::new(ErrorKind::NotFound, "no servers configured");
+
+        for server in &self.servers {
+            let url = format!("{}/{}", server.trim_end_matches('/'), self.filename);
+            // libalpm resumes from `<file>.part`, so downloads go to a name of our own
+            // that is removed again when the download fails
+            let part = NamedTempFile::new_in(dest)?;
+            match self.fetch_from(client, &url, part.as_file()) {
+                Ok(()) => {
+                    part.as_file()
+                        .set_permissions(fs::Permissions::from_mode(0o644))?;
+                    part.persist(&path).map_err(|err| err.error)?;
+                    return Ok(path);
+                }
+                Err(err) => last_err = io::Error::new(err.kind(), format!("{}: {}", url, err)),
+            }
+        }
+        Err(last_err)
+    }
+
+    fn fetch_from(&self, client: &Client, url: &str, part: &File) -> io::Result<()> {
+        let mut response = client
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| io::Error::new(ErrorKind::Other, err))?;
+
+        let mut writer = HashWriter {
+            inner: part,
+            hasher: Sha256::new(),
+            len: 0,
+        };
+        io::copy(&mut response, &mut writer)?;
+        writer.flush()?;
+
+        if self.size > 0 && writer.len != self.size as u64 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("expected {} bytes, got {}", self.size, writer.len),
+            ));
+        }
+        if let Some(sha256) = &self.sha256 {
+            let actual = format!("{:x}", writer.hasher.finalize());
+            if &actual != sha256 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("sha256 mismatch (expected {}, got {})", sha256, actual),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    /// Serve `bodies` to `connections` requests, keyed by request path
+    fn mirror(bodies: Vec<(&'static str, &'static [u8])>, connections: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                        break;
+                    }
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let stream = reader.get_mut();
+                match bodies.iter().find(|(name, _)| *name == path) {
+                    Some((_, body)) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .unwrap();
+                        stream.write_all(body).unwrap();
+                    }
+                    None => write!(
+                        stream,
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    )
+                    .unwrap(),
+                }
+            }
+        });
+        url
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn download(servers: Vec<String>, body: &[u8]) -> Download {
+        Download {
+            filename: "pkg.tar.zst".to_string(),
+            servers,
+            size: body.len() as i64,
+            sha256: Some(format!("{:x}", Sha256::digest(body))),
+        }
+    }
+
+    #[test]
+    fn downloads_and_verifies() {
+        let dest = tempfile::tempdir().unwrap();
+        // An interrupted libalpm download, which it resumes later
+        fs::write(dest.path().join("pkg.tar.zst.part"), "pack").unwrap();
+        let server = mirror(vec![("/repo/pkg.tar.zst", b"package")], 1);
+
+        let failed = download_all(
+            vec![download(vec![format!("{}/repo/", server)], b"package")],
+            dest.path(),
+            2,
+            true,
+        );
+
+        assert!(failed.is_empty());
+        assert_eq!(
+            fs::read(dest.path().join("pkg.tar.zst")).unwrap(),
+            b"package"
+        );
+        assert_eq!(entries(dest.path()), ["pkg.tar.zst", "pkg.tar.zst.part"]);
+        assert_eq!(
+            fs::read(dest.path().join("pkg.tar.zst.part")).unwrap(),
+            b"pack"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_next_mirror() {
+        let dest = tempfile::tempdir().unwrap();
+        let broken = mirror(vec![("/pkg.tar.zst", b"corrupt")], 1);
+        let missing = mirror(Vec::new(), 1);
+        let good = mirror(vec![("/pkg.tar.zst", b"package")], 1);
+
+        let failed = download_all(
+            vec![download(vec![broken, missing, good], b"package")],
+            dest.path(),
+            1,
+            false,
+        );
+
+        assert!(failed.is_empty());
+        assert_eq!(
+            fs::read(dest.path().join("pkg.tar.zst")).unwrap(),
+            b"package"
+        );
+        assert_eq!(entries(dest.path()), ["pkg.tar.zst"]);
+    }
+
+    #[test]
+    fn reports_downloads_failing_everywhere() {
+        let dest = tempfile::tempdir().unwrap();
+        let broken = mirror(vec![("/pkg.tar.zst", b"corrupt")], 1);
+
+        let failed = download_all(
+            vec![download(vec![broken], b"package")],
+            dest.path(),
+            1,
+            true,
+        );
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "pkg.tar.zst");
+        assert_eq!(failed[0].1.kind(), ErrorKind::InvalidData);
+        assert!(entries(dest.path()).is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{fmt, io};
+
+use crate::interrupt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can make an operation fail
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration could not be read, the message names the file and line
+    Config(String),
+    Alpm(alpm::Error),
+    Io(io::Error),
+    /// The AUR or a mirror could not be reached
+    Network(String),
+    /// Targets that are neither packages, groups nor provisions
+    TargetNotFound(Vec<String>),
+    /// The user declined a prompt
+    Aborted,
+    /// Ctrl+C was pressed during a transaction
+    Interrupted,
+    /// An operation failed, details have already been printed
+    Failed(String),
+}
+
+impl Error {
+    /// pacman exits with 1 on every kind of failure except interrupts
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Interrupted => interrupt::EXIT_CODE,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "config error: {}", message),
+            Error::Alpm(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Network(message) => write!(f, "{}", message),
+            Error::TargetNotFound(targets) => {
+                write!(f, "target not found: {}", targets.join(", "))
+            }
+            Error::Aborted => f.write_str("operation aborted"),
+            Error::Interrupted => f.write_str("interrupted"),
+            Error::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<alpm::Error> for Error {
+    fn from(err: alpm::Error) -> Self {
+        Error::Alpm(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<raur::Error> for Error {
+    fn from(err: raur::Error) -> Self {
+        Error::Network(format!("failed to query the AUR: {}", err))
+    }
+}
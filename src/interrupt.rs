@@ -0,0 +1,65 @@
+use alpm::{alpm_sys::*, Alpm};
+use std::{
+    process, ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    thread,
+};
+
+use crate::callbacks;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static ALPM_HANDLE: AtomicPtr<alpm_handle_t> = AtomicPtr::new(ptr::null_mut());
+/// Set while the handler uses `ALPM_HANDLE`, so it is not released underneath it
+static HANDLER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// pacman exits with 128 + SIGINT when interrupted
+pub const EXIT_CODE: i32 = 130;
+
+/// Forgets the handle when dropped, which must happen before the `Alpm` is released
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        ALPM_HANDLE.store(ptr::null_mut(), Ordering::SeqCst);
+        // A handler that loaded the handle before it was cleared finishes first
+        while HANDLER_RUNNING.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Install the Ctrl+C handler for `handle`
+pub fn install(handle: &Alpm) -> Guard {
+    ALPM_HANDLE.store(handle.as_alpm_handle_t(), Ordering::SeqCst);
+    ctrlc::set_handler(on_interrupt).expect("Could not install the interrupt handler");
+    Guard(())
+}
+
+/// Whether Ctrl+C was pressed
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+fn on_interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    callbacks::clear_progress();
+    // Show the cursor again in case a prompt or bar hid it
+    eprintln!("\x1b[?25h\nInterrupt signal received");
+
+    HANDLER_RUNNING.store(true, Ordering::SeqCst);
+    let handle = ALPM_HANDLE.load(Ordering::SeqCst);
+    if handle.is_null() {
+        process::exit(EXIT_CODE);
+    }
+    unsafe {
+        // A committing transaction stops after its current step, the caller
+        // then releases it and exits
+        if alpm_trans_interrupt(handle) == 0 {
+            HANDLER_RUNNING.store(false, Ordering::SeqCst);
+            return;
+        }
+        // Nothing was applied yet, only the lock has to go
+        alpm_unlock(handle);
+    }
+    process::exit(EXIT_CODE);
+}
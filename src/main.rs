@@ -1,53 +1,128 @@
 mod args;
+mod aur;
 mod callbacks;
 mod commands;
 mod config;
+mod download;
+mod error;
+mod interrupt;
+mod mtree;
+mod output;
+mod owners;
+mod pacman_conf;
 mod utils;
 
 use {
-    crate::{args::Args, commands::CommandHandler, config::Config},
-    alpm::{Alpm, SigLevel},
+    crate::{
+        args::Args,
+        commands::CommandHandler,
+        config::Config,
+        error::{Error, Result},
+    },
+    alpm::Alpm,
     clap::Clap,
-    std::{cell::RefCell, fs},
+    std::{cell::RefCell, fs, io, path::Path, process},
 };
 
 const DEFAULT_CONFIG_PATH: &str = "/etc/rpac.toml";
 
 fn main() {
     env_logger::init();
-    ctrlc::set_handler(|| panic!("Caught CTRL+C! Unwinding...")).unwrap();
     let opts: Args = args::Args::parse();
-    let config: Config = {
-        let config_path = opts
-            .config
-            .unwrap_or_else(|| DEFAULT_CONFIG_PATH.parse().unwrap());
-        let data = match fs::read_to_string(config_path.to_owned()) {
-            Ok(data) => data,
-            Err(err) => {
-                eprintln!(
-                    "Config was not found at {:?}: {:?}. Using defaults!",
-                    config_path, err
-                );
-                "".to_string()
-            }
-        };
+    if let Err(err) = run(opts) {
+        // Declining a prompt is not worth an error message and an interrupt was already reported
+        if !matches!(err, Error::Aborted | Error::Interrupted) {
+            eprintln!("error: {}", err);
+        }
+        process::exit(err.exit_code());
+    }
+}
+
+fn load_config(opts: &Args) -> Result<Config> {
+    let config_path = opts
+        .config
+        .clone()
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.parse().unwrap());
+    if opts.pacman_conf || config_path.extension().map_or(false, |ext| ext == "conf") {
+        return pacman_conf::parse(&config_path);
+    }
 
-        toml::from_str(data.as_str()).expect("Config parse error!")
+    let data = match fs::read_to_string(&config_path) {
+        Ok(data) => data,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!(
+                "Config was not found at {:?}. Using defaults!",
+                config_path
+            );
+            "".to_string()
+        }
+        Err(err) => return Err(config_error(&config_path, err)),
     };
+    Config::from_toml(data.as_str()).map_err(|err| config_error(&config_path, err))
+}
+
+fn config_error<E: std::fmt::Display>(path: &Path, err: E) -> Error {
+    Error::Config(format!("{}: {}", path.display(), err))
+}
+
+fn run(opts: Args) -> Result<()> {
+    if let Some(sysroot) = &opts.sysroot {
+        // Everything after this, including reading the config, happens inside the guest
+        std::os::unix::fs::chroot(sysroot).map_err(|err| {
+            Error::Failed(format!(
+                "could not change into the sysroot {}: {}",
+                sysroot.display(),
+                err
+            ))
+        })?;
+        std::env::set_current_dir("/")?;
+    }
+    let mut config = load_config(&opts)?;
+    config.apply_env().map_err(Error::Config)?;
+    config.apply_args(&opts);
+    if opts.debug_config {
+        config.print_debug();
+        return Ok(());
+    }
+
     let mut handle = Alpm::new(
-        config.paths.root.to_str().unwrap(),
-        config.paths.database.to_str().unwrap(),
+        config.paths.root.to_string_lossy().as_ref(),
+        config.paths.database.to_string_lossy().as_ref(),
     )
-    .expect("Could not obtain database lock");
+    .map_err(|err| Error::Failed(format!("failed to initialize alpm library ({})", err)))?;
     if let commands::Command::Files(_) = opts.command {
         handle.set_dbext(".files");
     } else {
         handle.set_dbext(".db");
     }
+    handle.set_arch(config.arch.as_str());
+    handle.set_gpgdir(config.paths.gpg.to_string_lossy().as_ref())?;
+    handle.set_logfile(config.paths.logfile.to_string_lossy().as_ref())?;
+    for dir in &config.paths.cache_dirs {
+        handle.add_cachedir(dir.to_string_lossy().as_ref())?;
+    }
+    for dir in &config.paths.hook_dirs {
+        handle.add_hookdir(dir.to_string_lossy().as_ref())?;
+    }
+    handle.set_use_syslog(config.syslog);
+    handle.set_disable_dl_timeout(!config.download_timeout);
+    handle.set_default_siglevel(config.siglevel())?;
+    for pkg in &config.ignore_pkg {
+        handle.add_ignorepkg(pkg.as_str())?;
+    }
+    for group in &config.ignore_group {
+        handle.add_ignoregroup(group.as_str())?;
+    }
+    for file in &config.no_upgrade {
+        handle.add_noupgrade(file.as_str())?;
+    }
+    for file in &config.no_extract {
+        handle.add_noextract(file.as_str())?;
+    }
     for db in &config.databases {
-        let registered_db = handle
-            .register_syncdb_mut(db.name.clone(), SigLevel::empty())
-            .unwrap();
+        let registered_db =
+            handle.register_syncdb_mut(db.name.clone(), config.database_siglevel(db))?;
+        registered_db.set_usage(db.usage())?;
         let servers = db
             .servers
             .iter()
@@ -56,11 +131,12 @@ fn main() {
             .collect::<Vec<String>>();
 
         for server in servers {
-            registered_db.add_server(server).unwrap();
+            registered_db.add_server(server)?;
         }
     }
-    utils::register_cbs(&handle);
-    //opts.command.handle(RefCell::new(handle), config);
-    //opts.command.handle(RefCell::new(handle), config);
-    opts.command.handle(RefCell::new(handle), config);
+    utils::register_cbs(&handle, config.no_confirm);
+    let handle = RefCell::new(handle);
+    // Dropped before `handle`, so Ctrl+C never reaches a released handle
+    let _interrupt = interrupt::install(&handle.borrow());
+    opts.command.handle(&handle, config)
 }
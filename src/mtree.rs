@@ -0,0 +1,201 @@
+use flate2::read::GzDecoder;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EntryType {
+    File,
+    Dir,
+    Link,
+}
+
+/// A file of a package as recorded in its `mtree`
+#[derive(Debug)]
+pub struct Entry {
+    /// Relative to the installation root, without a leading `/`
+    pub path: String,
+    pub kind: EntryType,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub time: Option<i64>,
+    pub link: Option<String>,
+    pub sha256: Option<String>,
+}
+
+/// Read a gzip compressed mtree, skipping the package metadata files
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut data = String::new();
+    GzDecoder::new(File::open(path)?).read_to_string(&mut data)?;
+    Ok(parse(&data))
+}
+
+pub fn parse(data: &str) -> Vec<Entry> {
+    let mut defaults: HashMap<String, String> = HashMap::new();
+    let mut entries = Vec::new();
+
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let first = words.next().unwrap();
+        match first {
+            "/set" => {
+                for (key, value) in words.filter_map(keyword) {
+                    defaults.insert(key.to_string(), value.to_string());
+                }
+            }
+            "/unset" => {
+                for key in words {
+                    defaults.remove(key);
+                }
+            }
+            _ => {
+                let path = unescape(first.trim_start_matches("./"));
+                // .PKGINFO, .MTREE, .INSTALL and friends are not installed
+                if path.starts_with('.') {
+                    continue;
+                }
+                let mut keywords = defaults.clone();
+                for (key, value) in words.filter_map(keyword) {
+                    keywords.insert(key.to_string(), value.to_string());
+                }
+                entries.push(entry(path, &keywords));
+            }
+        }
+    }
+    entries
+}
+
+fn entry(path: String, keywords: &HashMap<String, String>) -> Entry {
+    let get = |key: &str| keywords.get(key).map(String::as_str);
+    Entry {
+        path,
+        kind: match get("type") {
+            Some("dir") => EntryType::Dir,
+            Some("link") => EntryType::Link,
+            _ => EntryType::File,
+        },
+        mode: get("mode").and_then(|mode| u32::from_str_radix(mode, 8).ok()),
+        uid: get("uid").and_then(|uid| uid.parse().ok()),
+        gid: get("gid").and_then(|gid| gid.parse().ok()),
+        size: get("size").and_then(|size| size.parse().ok()),
+        // Seconds with a fractional part, only whole seconds are compared
+        time: get("time").and_then(|time| time.splitn(2, '.').next()?.parse().ok()),
+        link: get("link").map(unescape),
+        sha256: get("sha256digest").map(String::from),
+    }
+}
+
+fn keyword(word: &str) -> Option<(&str, &str)> {
+    let mut split = word.splitn(2, '=');
+    Some((split.next()?, split.next()?))
+}
+
+/// Undo the `\ooo` octal escaping mtree uses for special characters
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() {
+            let octal = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(octal, 8) {
+                result.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    const MTREE: &str = r"#mtree
+/set type=file uid=0 gid=0 mode=644
+./.BUILDINFO time=1600000000.0 size=4096 md5digest=0 sha256digest=0
+./.PKGINFO time=1600000000.0 size=512 md5digest=0 sha256digest=0
+./usr time=1600000000.0 mode=755 type=dir
+./usr/bin/tool time=1600000000.5 mode=755 size=5 sha256digest=abc
+./usr/share/doc/with\040space time=1600000001.0 size=0
+/set uid=1000
+./usr/lib/libtool.so time=1600000002.0 type=link link=libtool\056so.1
+/unset mode gid
+./usr/share/.hidden time=1600000003.0
+";
+
+    #[test]
+    fn parse_applies_set_and_unset() {
+        let entries = parse(MTREE);
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "usr",
+                "usr/bin/tool",
+                "usr/share/doc/with space",
+                "usr/lib/libtool.so",
+                "usr/share/.hidden"
+            ]
+        );
+
+        assert_eq!(entries[0].kind, EntryType::Dir);
+        assert_eq!(entries[0].mode, Some(0o755));
+
+        let tool = &entries[1];
+        assert_eq!(tool.kind, EntryType::File);
+        assert_eq!((tool.uid, tool.gid), (Some(0), Some(0)));
+        assert_eq!(tool.size, Some(5));
+        assert_eq!(tool.time, Some(1_600_000_000));
+        assert_eq!(tool.sha256.as_deref(), Some("abc"));
+
+        assert_eq!(entries[2].mode, Some(0o644));
+
+        let link = &entries[3];
+        assert_eq!(link.kind, EntryType::Link);
+        assert_eq!(link.uid, Some(1000));
+        assert_eq!(link.link.as_deref(), Some("libtool.so.1"));
+
+        let hidden = &entries[4];
+        assert_eq!(hidden.kind, EntryType::File);
+        assert_eq!(hidden.uid, Some(1000));
+        assert_eq!((hidden.mode, hidden.gid), (None, None));
+    }
+
+    #[test]
+    fn unescape_octal() {
+        assert_eq!(unescape(r"a\040b"), "a b");
+        assert_eq!(unescape(r"caf\303\251"), "café");
+        assert_eq!(unescape(r"\134"), "\\");
+        // Incomplete or invalid escapes are kept as they are
+        assert_eq!(unescape(r"a\04"), r"a\04");
+        assert_eq!(unescape(r"a\999"), r"a\999");
+    }
+
+    #[test]
+    fn read_gzip_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mtree");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(MTREE.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[1].path, "usr/bin/tool");
+    }
+}
@@ -0,0 +1,355 @@
+use alpm::Package;
+use serde::Serialize;
+use std::{fmt::Display, str::FromStr};
+
+use crate::{aur, utils::EnumFormatter};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Text,
+    /// A single JSON array of all records
+    Json,
+    /// One JSON record per line, printed as soon as it is found
+    Ndjson,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Text
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!("unknown output format '{}'", value)),
+        }
+    }
+}
+
+/// Collects the records of a command in the requested format
+pub struct Output {
+    format: OutputFormat,
+    records: Vec<serde_json::Value>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    pub fn emit<T: Serialize>(&mut self, record: &T) {
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Json => self
+                .records
+                .push(serde_json::to_value(record).expect("records serialize to JSON")),
+            OutputFormat::Ndjson => println!(
+                "{}",
+                serde_json::to_string(record).expect("records serialize to JSON")
+            ),
+        }
+    }
+
+    /// Print the collected records, must be called once the command is done
+    pub fn finish(self) {
+        if self.format == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&self.records).expect("records serialize to JSON")
+            );
+        }
+    }
+}
+
+/// Everything the text output shows about a package, from a database or the AUR
+#[derive(Serialize, Default)]
+pub struct PackageRecord {
+    pub repository: Option<String>,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub architecture: Option<String>,
+    pub url: Option<String>,
+    pub licenses: Vec<String>,
+    pub groups: Vec<String>,
+    pub provides: Vec<String>,
+    pub depends: Vec<String>,
+    pub make_depends: Vec<String>,
+    pub check_depends: Vec<String>,
+    pub optional_deps: Vec<String>,
+    pub required_by: Vec<String>,
+    pub optional_for: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub replaces: Vec<String>,
+    pub backup: Vec<String>,
+    pub download_size: Option<i64>,
+    pub installed_size: Option<i64>,
+    pub packager: Option<String>,
+    pub build_date: Option<i64>,
+    pub install_date: Option<i64>,
+    pub install_reason: Option<String>,
+    pub install_script: Option<bool>,
+    pub validated_by: Vec<String>,
+    pub maintainer: Option<String>,
+    pub votes: Option<u32>,
+    pub popularity: Option<f64>,
+    pub out_of_date: Option<i64>,
+    pub first_submitted: Option<i64>,
+    pub last_modified: Option<i64>,
+    /// The installed version, when looking at a sync or AUR package
+    pub installed_version: Option<String>,
+    /// The version an upgrade would install
+    pub new_version: Option<String>,
+    /// Relative to the installation root, like the files of a `FilesRecord`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+}
+
+impl PackageRecord {
+    pub fn from_alpm(package: &Package) -> Self {
+        let is_local = package.db().map_or(false, |db| db.name() == "local");
+        Self {
+            repository: package.db().map(|db| db.name().to_string()),
+            name: package.name().to_string(),
+            version: package.version().to_string(),
+            description: package.desc().map(String::from),
+            architecture: package.arch().map(String::from),
+            url: package.url().map(String::from),
+            licenses: strings(package.licenses()),
+            groups: strings(package.groups()),
+            provides: strings(package.provides()),
+            depends: strings(package.depends()),
+            optional_deps: strings(package.optdepends()),
+            required_by: strings(package.required_by()),
+            optional_for: strings(package.optional_for()),
+            conflicts: strings(package.conflicts()),
+            replaces: strings(package.replaces()),
+            backup: package
+                .backup()
+                .map(|backup| backup.name().to_string())
+                .collect(),
+            download_size: Some(package.download_size()).filter(|_| !is_local),
+            installed_size: Some(package.isize()),
+            packager: package.packager().map(String::from),
+            build_date: Some(package.build_date()),
+            install_date: package.install_date(),
+            install_reason: Some(EnumFormatter::from(package.reason()).to_string())
+                .filter(|_| is_local),
+            install_script: Some(package.has_scriptlet()).filter(|_| is_local),
+            validated_by: EnumFormatter::from(package.validation())
+                .to_string()
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_aur(package: &raur::Package) -> Self {
+        Self {
+            repository: Some(aur::REPO_NAME.to_string()),
+            name: package.name.clone(),
+            version: package.version.clone(),
+            description: package.description.clone(),
+            url: package.url.clone(),
+            licenses: package.license.clone(),
+            groups: package.groups.clone(),
+            provides: package.provides.clone(),
+            depends: package.depends.clone(),
+            make_depends: package.make_depends.clone(),
+            check_depends: package.check_depends.clone(),
+            optional_deps: package.opt_depends.clone(),
+            conflicts: package.conflicts.clone(),
+            replaces: package.replaces.clone(),
+            maintainer: package.maintainer.clone(),
+            votes: Some(package.num_votes),
+            popularity: Some(package.popularity),
+            out_of_date: package.out_of_date,
+            first_submitted: Some(package.first_submitted),
+            last_modified: Some(package.last_modified),
+            ..Self::default()
+        }
+    }
+}
+
+/// A file and the installed package that owns it (`-Qo`)
+#[derive(Serialize)]
+pub struct OwnershipRecord {
+    pub path: String,
+    pub package: String,
+    pub version: String,
+}
+
+/// Files of a sync database package (`-F`, `-Fl`)
+#[derive(Serialize)]
+pub struct FilesRecord {
+    pub repository: String,
+    pub name: String,
+    pub version: String,
+    pub installed: bool,
+    /// Relative to the installation root, as stored in the databases
+    pub files: Vec<String>,
+}
+
+/// The file names of a record, always relative to the installation root
+pub fn file_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    names
+        .into_iter()
+        .map(|name| name.trim_start_matches('/').to_string())
+        .collect()
+}
+
+fn strings<I: IntoIterator>(values: I) -> Vec<String>
+where
+    I::Item: Display,
+{
+    values.into_iter().map(|value| value.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn package_record_schema() {
+        let record = PackageRecord {
+            repository: Some("extra".to_string()),
+            name: "ripgrep".to_string(),
+            version: "12.1.1-1".to_string(),
+            depends: vec!["gcc-libs".to_string(), "pcre2".to_string()],
+            installed_size: Some(4_412_394),
+            installed_version: Some("12.1.0-1".to_string()),
+            ..PackageRecord::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            json!({
+                "repository": "extra",
+                "name": "ripgrep",
+                "version": "12.1.1-1",
+                "description": null,
+                "architecture": null,
+                "url": null,
+                "licenses": [],
+                "groups": [],
+                "provides": [],
+                "depends": ["gcc-libs", "pcre2"],
+                "make_depends": [],
+                "check_depends": [],
+                "optional_deps": [],
+                "required_by": [],
+                "optional_for": [],
+                "conflicts": [],
+                "replaces": [],
+                "backup": [],
+                "download_size": null,
+                "installed_size": 4_412_394,
+                "packager": null,
+                "build_date": null,
+                "install_date": null,
+                "install_reason": null,
+                "install_script": null,
+                "validated_by": [],
+                "maintainer": null,
+                "votes": null,
+                "popularity": null,
+                "out_of_date": null,
+                "first_submitted": null,
+                "last_modified": null,
+                "installed_version": "12.1.0-1",
+                "new_version": null,
+            })
+        );
+    }
+
+    #[test]
+    fn package_record_files_are_only_present_when_listed() {
+        let record = PackageRecord {
+            files: Some(vec!["usr/bin/rg".to_string()]),
+            ..PackageRecord::default()
+        };
+        let value = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(value["files"], json!(["usr/bin/rg"]));
+        assert!(serde_json::to_value(&PackageRecord::default())
+            .unwrap()
+            .get("files")
+            .is_none());
+    }
+
+    #[test]
+    fn file_names_are_relative_in_every_record() {
+        let files = file_names(vec!["usr/", "/usr/bin/rg"]);
+        let package = PackageRecord {
+            files: Some(files.clone()),
+            ..PackageRecord::default()
+        };
+        let files_record = FilesRecord {
+            repository: "extra".to_string(),
+            name: "ripgrep".to_string(),
+            version: "12.1.1-1".to_string(),
+            installed: true,
+            files,
+        };
+
+        let package = serde_json::to_value(&package).unwrap();
+        let files_record = serde_json::to_value(&files_record).unwrap();
+        assert_eq!(package["files"], json!(["usr/", "usr/bin/rg"]));
+        assert_eq!(package["files"], files_record["files"]);
+    }
+
+    #[test]
+    fn ownership_record_schema() {
+        let record = OwnershipRecord {
+            path: "/usr/bin/rg".to_string(),
+            package: "ripgrep".to_string(),
+            version: "12.1.1-1".to_string(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            json!({
+                "path": "/usr/bin/rg",
+                "package": "ripgrep",
+                "version": "12.1.1-1",
+            })
+        );
+    }
+
+    #[test]
+    fn files_record_schema() {
+        let record = FilesRecord {
+            repository: "extra".to_string(),
+            name: "ripgrep".to_string(),
+            version: "12.1.1-1".to_string(),
+            installed: true,
+            files: vec!["usr/".to_string(), "usr/bin/rg".to_string()],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            json!({
+                "repository": "extra",
+                "name": "ripgrep",
+                "version": "12.1.1-1",
+                "installed": true,
+                "files": ["usr/", "usr/bin/rg"],
+            })
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use alpm::Alpm;
+use path_absolutize::Absolutize;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::error::Result;
+
+/// An installed package owning a path
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Owner {
+    pub name: String,
+    pub version: String,
+}
+
+/// Maps every path of the local database to the packages owning it
+#[derive(Serialize, Deserialize)]
+pub struct OwnerIndex {
+    dbpath: String,
+    /// Modification time of the local database when the index was built
+    mtime: Option<(u64, u32)>,
+    /// Paths relative to the root, without a trailing `/` for directories
+    owners: HashMap<String, Vec<Owner>>,
+}
+
+impl OwnerIndex {
+    /// Use the cached index unless the local database changed since it was written
+    pub fn load(handle: &Alpm) -> Result<Self> {
+        let mtime = local_mtime(handle);
+        let cache = cache_path();
+        if let Some(index) = cache.as_deref().and_then(read_cache) {
+            if index.dbpath == handle.dbpath() && mtime.is_some() && index.mtime == mtime {
+                return Ok(index);
+            }
+        }
+
+        let index = Self::build(handle, mtime)?;
+        if let Some(cache) = cache.filter(|_| mtime.is_some()) {
+            // Without a cache the next lookup is only slower
+            if let Err(err) = index.write(&cache) {
+                log::debug!("could not write {}: {}", cache.display(), err);
+            }
+        }
+        Ok(index)
+    }
+
+    fn build(handle: &Alpm, mtime: Option<(u64, u32)>) -> Result<Self> {
+        let mut owners: HashMap<String, Vec<Owner>> = HashMap::new();
+        for package in handle.localdb().pkgs()? {
+            for file in package.files().files() {
+                owners
+                    .entry(file.name().trim_end_matches('/').to_string())
+                    .or_default()
+                    .push(Owner {
+                        name: package.name().to_string(),
+                        version: package.version().to_string(),
+                    });
+            }
+        }
+        Ok(Self {
+            dbpath: handle.dbpath().to_string(),
+            mtime,
+            owners,
+        })
+    }
+
+    /// The packages owning `path`, directories usually have several
+    pub fn owners(&self, root: &Path, path: &Path) -> &[Owner] {
+        path.strip_prefix(root)
+            .ok()
+            .and_then(|relative| self.owners.get(relative.to_string_lossy().as_ref()))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+/// Make `path` absolute and resolve symlinks in its parents, so `/bin/ls`
+/// becomes `/usr/bin/ls` while a symlink itself is still looked up as is
+pub fn normalize(path: &Path) -> PathBuf {
+    let path = path
+        .absolutize()
+        .map_or_else(|_| path.to_path_buf(), |path| path.into_owned());
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            fs::canonicalize(parent).map_or_else(|_| path.clone(), |parent| parent.join(name))
+        }
+        _ => path,
+    }
+}
+
+fn local_mtime(handle: &Alpm) -> Option<(u64, u32)> {
+    let modified = fs::metadata(Path::new(handle.dbpath()).join("local"))
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache.join("rpac").join("owners.json"))
+}
+
+fn read_cache(path: &Path) -> Option<OwnerIndex> {
+    serde_json::from_reader(BufReader::new(File::open(path).ok()?)).ok()
+}
@@ -0,0 +1,399 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{parse_usage, validate_siglevel, Config, Database, Source},
+    error::{self, Error},
+};
+
+/// pacman gives up on includes nested this deep
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+struct Repo {
+    name: String,
+    servers: Vec<String>,
+    usage: Vec<String>,
+    siglevel: Option<Vec<String>>,
+}
+
+/// Parse a pacman.conf, following `Include` directives, into a `Config`
+pub fn parse(path: &Path) -> error::Result<Config> {
+    let mut parser = Parser {
+        config: Config::default(),
+        section: None,
+        repos: Vec::new(),
+        cache_dirs_set: false,
+        hook_dirs_set: false,
+    };
+    parser
+        .read(path, 0)
+        .map_err(|err| Error::Config(err.to_string()))?;
+
+    parser.config.databases = parser
+        .repos
+        .into_iter()
+        .map(|repo| {
+            let usage = if repo.usage.is_empty() {
+                vec!["All".to_string()]
+            } else {
+                repo.usage
+            };
+            Database::new(repo.name, repo.servers, usage, repo.siglevel)
+        })
+        .collect::<io::Result<_>>()
+        .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+    Ok(parser.config)
+}
+
+struct Parser {
+    config: Config,
+    section: Option<String>,
+    repos: Vec<Repo>,
+    cache_dirs_set: bool,
+    hook_dirs_set: bool,
+}
+
+impl Parser {
+    fn read(&mut self, path: &Path, depth: usize) -> io::Result<()> {
+        let data = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+
+        for (index, line) in data.lines().enumerate() {
+            let error = |message: String| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), index + 1, message),
+                )
+            };
+            let line = line.splitn(2, '#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = &line[1..line.len() - 1];
+                if name.is_empty() {
+                    return Err(error("empty section name".to_string()));
+                }
+                if name != "options" {
+                    self.repos.push(Repo {
+                        name: name.to_string(),
+                        servers: Vec::new(),
+                        usage: Vec::new(),
+                        siglevel: None,
+                    });
+                }
+                self.section = Some(name.to_string());
+                continue;
+            }
+
+            let mut split = line.splitn(2, '=');
+            let key = split.next().unwrap().trim();
+            let value = split.next().map(str::trim);
+            let section = match &self.section {
+                Some(section) => section.clone(),
+                None => return Err(error(format!("'{}' is not in a section", key))),
+            };
+
+            if key == "Include" {
+                let value = value.ok_or_else(|| error("Include requires a value".to_string()))?;
+                // Also stops files that include themselves
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(error(format!(
+                        "Include nested more than {} levels deep",
+                        MAX_INCLUDE_DEPTH
+                    )));
+                }
+                for include in expand(Path::new(value))? {
+                    self.read(&include, depth + 1)?;
+                }
+            } else if section == "options" {
+                self.option(key, value).map_err(error)?;
+            } else {
+                self.repo(key, value).map_err(error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn option(&mut self, key: &str, value: Option<&str>) -> Result<(), String> {
+        let config = &mut self.config;
+        let required = || value.ok_or_else(|| format!("{} requires a value", key));
+        let list = || value.unwrap_or("").split_whitespace().map(String::from);
+
+        match key {
+            "RootDir" => config.paths.root = PathBuf::from(required()?),
+            "DBPath" => config.paths.database = PathBuf::from(required()?),
+            "GPGDir" => config.paths.gpg = PathBuf::from(required()?),
+            "LogFile" => config.paths.logfile = PathBuf::from(required()?),
+            "CacheDir" => {
+                if !self.cache_dirs_set {
+                    config.paths.cache_dirs.clear();
+                    self.cache_dirs_set = true;
+                }
+                config.paths.cache_dirs.extend(list().map(PathBuf::from));
+            }
+            "HookDir" => {
+                if !self.hook_dirs_set {
+                    config.paths.hook_dirs.clear();
+                    self.hook_dirs_set = true;
+                }
+                config.paths.hook_dirs.extend(list().map(PathBuf::from));
+            }
+            "Architecture" => {
+                let arch = list().next().ok_or_else(|| format!("{} requires a value", key))?;
+                if arch != "auto" {
+                    config.arch = arch;
+                }
+            }
+            "IgnorePkg" => config.ignore_pkg.extend(list()),
+            "IgnoreGroup" => config.ignore_group.extend(list()),
+            "HoldPkg" => config.hold_pkg.extend(list()),
+            "NoExtract" => config.no_extract.extend(list()),
+            "NoUpgrade" => config.no_upgrade.extend(list()),
+            "ParallelDownloads" => {
+                config.parallel_downloads = required()?
+                    .parse()
+                    .map_err(|err| format!("invalid ParallelDownloads: {}", err))?
+            }
+            "UseSyslog" => config.syslog = true,
+            "Color" => config.color = true,
+            "DisableDownloadTimeout" => config.download_timeout = false,
+            "SigLevel" => {
+                // Applied on top of the default, like pacman does
+                let values: Vec<String> = list().collect();
+                validate_siglevel(&values)?;
+                config.siglevel.extend(values);
+            }
+            "LocalFileSigLevel" | "RemoteFileSigLevel" | "XferCommand"
+            | "CleanMethod" | "CheckSpace" | "VerbosePkgLists" | "ILoveCandy" | "NoProgressBar"
+            | "TotalDownload" => log::debug!("ignoring unsupported pacman.conf option {}", key),
+            _ => log::warn!("unknown pacman.conf option {}", key),
+        }
+        if let Some(setting) = setting(key) {
+            config.sources.insert(setting, Source::ConfigFile);
+        }
+        Ok(())
+    }
+
+    fn repo(&mut self, key: &str, value: Option<&str>) -> Result<(), String> {
+        let repo = self.repos.last_mut().unwrap();
+        let required = || value.ok_or_else(|| format!("{} requires a value", key));
+        let list = || -> Result<Vec<String>, String> {
+            Ok(required()?.split_whitespace().map(String::from).collect())
+        };
+        match key {
+            "Server" => repo.servers.push(required()?.to_string()),
+            "SigLevel" => {
+                let values = list()?;
+                validate_siglevel(&values)?;
+                repo.siglevel.get_or_insert_with(Vec::new).extend(values);
+            }
+            "Usage" => {
+                let values = list()?;
+                parse_usage(&values)?;
+                repo.usage.extend(values);
+            }
+            // Newer pacman versions add keys like CacheServer
+            _ => log::warn!("unknown pacman.conf repository option {}", key),
+        }
+        Ok(())
+    }
+}
+
+/// The overridable setting a pacman.conf option maps to
+fn setting(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "RootDir" => "root",
+        "DBPath" => "dbpath",
+        "CacheDir" => "cachedir",
+        "HookDir" => "hookdir",
+        "GPGDir" => "gpgdir",
+        "LogFile" => "logfile",
+        "Architecture" => "arch",
+        "DisableDownloadTimeout" => "disable_download_timeout",
+        _ => return None,
+    })
+}
+
+/// Expand a `*` in the file name of an `Include` path
+fn expand(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let pattern = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let star = match pattern.find('*') {
+        Some(star) => star,
+        None => return Ok(vec![path.to_path_buf()]),
+    };
+    let (prefix, suffix) = (&pattern[..star], &pattern[star + 1..]);
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(path.parent().unwrap_or_else(|| Path::new(".")))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name().map_or(false, |name| {
+                let name = name.to_string_lossy();
+                name.starts_with(prefix) && name.ends_with(suffix)
+            })
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, data: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "pacman.conf",
+            "[options]\n\
+             RootDir = /mnt\n\
+             Architecture = auto\n\
+             CacheDir = /mnt/cache/\n\
+             CacheDir = /srv/cache/\n\
+             IgnorePkg = linux linux-headers\n\
+             IgnorePkg = glibc\n\
+             ParallelDownloads = 5\n\
+             DisableDownloadTimeout\n\
+             ILoveCandy\n",
+        );
+
+        let config = parse(&path).unwrap();
+        assert_eq!(config.paths.root, PathBuf::from("/mnt"));
+        assert_eq!(config.arch, Config::default().arch);
+        assert_eq!(
+            config.paths.cache_dirs,
+            [PathBuf::from("/mnt/cache/"), PathBuf::from("/srv/cache/")]
+        );
+        assert_eq!(config.ignore_pkg, ["linux", "linux-headers", "glibc"]);
+        assert_eq!(config.parallel_downloads, 5);
+        assert!(!config.download_timeout);
+        assert_eq!(config.source("root"), Source::ConfigFile);
+        assert_eq!(config.source("cachedir"), Source::ConfigFile);
+        assert_eq!(config.source("arch"), Source::ConfigFile);
+        assert_eq!(config.source("dbpath"), Source::Default);
+    }
+
+    #[test]
+    fn architecture() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "pacman.conf",
+            "[options]\nArchitecture = aarch64 armv7h\n",
+        );
+
+        assert_eq!(parse(&path).unwrap().arch, "aarch64");
+    }
+
+    #[test]
+    fn includes_are_expanded_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("repos")).unwrap();
+        write(
+            &dir.path().join("repos"),
+            "b.conf",
+            "[extra]\nServer = http://b.example.com\n",
+        );
+        write(
+            &dir.path().join("repos"),
+            "a.conf",
+            "[core]\nServer = http://a.example.com\n",
+        );
+        write(&dir.path().join("repos"), "ignored.txt", "[ignored]\n");
+        let mirrorlist = write(dir.path(), "mirrorlist", "Server = http://m.example.com\n");
+        let path = write(
+            dir.path(),
+            "pacman.conf",
+            &format!(
+                "[options]\n\
+                 Include = {}/repos/*.conf\n\
+                 [community]\n\
+                 Include = {}\n",
+                dir.path().display(),
+                mirrorlist.display()
+            ),
+        );
+
+        let config = parse(&path).unwrap();
+        let repos: Vec<_> = config
+            .databases
+            .iter()
+            .map(|db| (db.name.as_str(), db.servers.clone()))
+            .collect();
+        assert_eq!(
+            repos,
+            [
+                ("core", vec!["http://a.example.com".to_string()]),
+                ("extra", vec!["http://b.example.com".to_string()]),
+                ("community", vec!["http://m.example.com".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive_includes_are_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pacman.conf");
+        write(
+            dir.path(),
+            "pacman.conf",
+            &format!("[options]\nInclude = {}\n", path.display()),
+        );
+
+        let err = parse(&path).err().unwrap().to_string();
+        assert!(err.contains("pacman.conf:2: Include nested"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_repo_options_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pacman.conf");
+        fs::write(
+            &path,
+            "[options]\n\
+             Architecture = x86_64\n\
+             \n\
+             [core]\n\
+             CacheServer = http://cache.example.com/$repo/os/$arch\n\
+             Server = http://mirror.example.com/$repo/os/$arch\n\
+             SigLevel = Never\n",
+        )
+        .unwrap();
+
+        let config = parse(&path).unwrap();
+        assert_eq!(config.databases.len(), 1);
+        let core = &config.databases[0];
+        assert_eq!(core.name, "core");
+        assert_eq!(core.servers, ["http://mirror.example.com/$repo/os/$arch"]);
+        assert_eq!(core.siglevel, Some(vec!["Never".to_string()]));
+    }
+
+    #[test]
+    fn known_repo_options_require_a_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pacman.conf");
+        fs::write(&path, "[core]\nServer\n").unwrap();
+
+        let err = parse(&path).err().unwrap().to_string();
+        assert!(
+            err.contains("pacman.conf:2: Server requires a value"),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,10 +1,27 @@
-use alpm::{Alpm, Depend, Package, PackageReason, PackageValidation,AlpmList};
-use std::{fmt::Write, path::PathBuf};
+use alpm::{
+    vercmp, Alpm, AlpmList, CommitReturn, DepMod, Depend, FileConflictType, Package, PackageFrom,
+    PackageReason, PackageValidation, PrepareReturn, TransFlag,
+};
+use dialoguer::Confirm;
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fmt::Write,
+    fs::{self, File},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
 
-use crate::callbacks::*;
+use crate::{
+    callbacks::*,
+    error::{Error, Result},
+    interrupt, mtree,
+};
 
-pub fn register_cbs(handle: &Alpm) {
-    init(handle);
+pub fn register_cbs(handle: &Alpm, no_confirm: bool) {
+    init(handle, no_confirm);
     QuestionCallback::register();
     LogCallback::register();
     DlCallback::register();
@@ -82,7 +99,38 @@ pub fn check(full: bool, package: &Package, handle: &Alpm) -> usize {
     let mut errors = 0;
 
     if full {
-        todo!("Implement Full check using mtree")
+        let mtree = PathBuf::from(handle.dbpath())
+            .join("local")
+            .join(format!("{}-{}", package.name(), package.version()))
+            .join("mtree");
+        let entries = match mtree::read(&mtree) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!(
+                    "{}: could not read {} ({})",
+                    package.name(),
+                    mtree.display(),
+                    err
+                );
+                return 1;
+            }
+        };
+        let backup: Vec<&str> = package.backup().map(|backup| backup.name()).collect();
+        for entry in entries {
+            let is_backup = backup.contains(&entry.path.as_str());
+            let altered = check_entry(&root, &entry, is_backup);
+            for attribute in &altered {
+                eprintln!(
+                    "{}: {} ({} mismatch)",
+                    package.name(),
+                    root.join(&entry.path).display(),
+                    attribute
+                );
+            }
+            if !altered.is_empty() {
+                errors += 1;
+            }
+        }
     } else {
         for file in package.files().files() {
             let path = root.join(file.name());
@@ -102,3 +150,649 @@ pub fn check(full: bool, package: &Package, handle: &Alpm) -> usize {
     }
     errors
 }
+
+/// Compare a file against its mtree entry, returning the mismatched attributes
+fn check_entry(root: &Path, entry: &mtree::Entry, is_backup: bool) -> Vec<&'static str> {
+    let path = root.join(&entry.path);
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return vec!["Existence"],
+    };
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        mtree::EntryType::Link
+    } else if file_type.is_dir() {
+        mtree::EntryType::Dir
+    } else {
+        mtree::EntryType::File
+    };
+    if kind != entry.kind {
+        return vec!["File type"];
+    }
+
+    let mut altered = Vec::new();
+    // Symlink permissions are meaningless on Linux
+    if kind != mtree::EntryType::Link
+        && entry.mode.map_or(false, |mode| mode != metadata.mode() & 0o7777)
+    {
+        altered.push("Permissions");
+    }
+    if entry.uid.map_or(false, |uid| uid != metadata.uid()) {
+        altered.push("UID");
+    }
+    if entry.gid.map_or(false, |gid| gid != metadata.gid()) {
+        altered.push("GID");
+    }
+    match kind {
+        mtree::EntryType::Link => {
+            let target = fs::read_link(&path).ok();
+            if entry.link.as_ref().map(PathBuf::from) != target {
+                altered.push("Symlink path");
+            }
+        }
+        // Backup files are expected to be edited, only their metadata is checked
+        mtree::EntryType::File if !is_backup => {
+            if entry.time.map_or(false, |time| time != metadata.mtime()) {
+                altered.push("Modification time");
+            }
+            if entry.size.map_or(false, |size| size != metadata.size()) {
+                altered.push("Size");
+            }
+            if let Some(sha256) = &entry.sha256 {
+                let mut hasher = Sha256::new();
+                let hashed =
+                    File::open(&path).and_then(|mut file| io::copy(&mut file, &mut hasher));
+                if hashed.is_err() || format!("{:x}", hasher.finalize()) != *sha256 {
+                    altered.push("SHA256 checksum");
+                }
+            }
+        }
+        _ => {}
+    }
+    altered
+}
+
+/// The local database lookups `orphans` needs
+pub trait LocalPackages {
+    /// Names of all installed packages
+    fn installed(&self) -> Result<Vec<String>>;
+    /// Names of the explicitly installed packages
+    fn explicit(&self) -> Result<Vec<String>>;
+    /// Dependencies of the installed package `name`, with its optdepends if `optional`
+    fn dependencies(&self, name: &str, optional: bool) -> Result<Vec<String>>;
+    /// The installed package satisfying `dep`
+    fn satisfier(&self, dep: &str) -> Result<Option<String>>;
+}
+
+impl LocalPackages for Alpm {
+    fn installed(&self) -> Result<Vec<String>> {
+        Ok(self
+            .localdb()
+            .pkgs()?
+            .map(|package| package.name().to_string())
+            .collect())
+    }
+
+    fn explicit(&self) -> Result<Vec<String>> {
+        Ok(self
+            .localdb()
+            .pkgs()?
+            .filter(|package| package.reason() == PackageReason::Explicit)
+            .map(|package| package.name().to_string())
+            .collect())
+    }
+
+    fn dependencies(&self, name: &str, optional: bool) -> Result<Vec<String>> {
+        let package = self.localdb().pkg(name)?;
+        let depends = package.depends().map(|dep| dep.to_string());
+        let optdepends = package
+            .optdepends()
+            .filter(|_| optional)
+            .map(|dep| dep.name().to_string());
+        Ok(depends.chain(optdepends).collect())
+    }
+
+    fn satisfier(&self, dep: &str) -> Result<Option<String>> {
+        Ok(self
+            .localdb()
+            .pkgs()?
+            .find_satisfier(dep)
+            .map(|package| package.name().to_string()))
+    }
+}
+
+/// Installed dependencies that no explicitly installed package needs, directly or
+/// through other dependencies, so dependency cycles nothing uses are found too
+pub fn orphans<L: LocalPackages>(handle: &L, optional: bool) -> Result<HashSet<String>> {
+    let mut stack = handle.explicit()?;
+    let mut reachable: HashSet<String> = stack.iter().cloned().collect();
+
+    while let Some(name) = stack.pop() {
+        for dep in handle.dependencies(&name, optional)? {
+            if let Some(satisfier) = handle.satisfier(&dep)? {
+                if reachable.insert(satisfier.clone()) {
+                    stack.push(satisfier);
+                }
+            }
+        }
+    }
+
+    Ok(handle
+        .installed()?
+        .into_iter()
+        .filter(|name| !reachable.contains(name))
+        .collect())
+}
+
+/// Whether `name` at `version` satisfies `dep`, an unversioned provide only
+/// satisfies unversioned dependencies like in libalpm
+pub fn satisfies(dep: &Depend, name: &str, version: Option<&str>) -> bool {
+    if dep.name() != name {
+        return false;
+    }
+    let wanted = match dep.version() {
+        Some(wanted) if dep.depmod() != DepMod::Any => wanted,
+        _ => return true,
+    };
+    let version = match version {
+        Some(version) => version,
+        None => return false,
+    };
+    let ordering = vercmp(version.to_string(), wanted.as_str().to_string());
+    match dep.depmod() {
+        DepMod::Any => true,
+        DepMod::Eq => ordering == Ordering::Equal,
+        DepMod::Ge => ordering != Ordering::Less,
+        DepMod::Le => ordering != Ordering::Greater,
+        DepMod::Gt => ordering == Ordering::Greater,
+        DepMod::Lt => ordering == Ordering::Less,
+    }
+}
+
+pub fn refresh_databases(handle: &mut Alpm, force: bool) -> Result<()> {
+    let mut failed = Vec::new();
+    for mut db in handle.syncdbs_mut() {
+        if let Err(err) = db.update(force) {
+            eprintln!("failed to update {} ({})", db.name(), err);
+            failed.push(db.name().to_string());
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Failed(format!(
+            "failed to synchronize databases: {}",
+            failed.join(", ")
+        )))
+    }
+}
+
+pub fn format_size(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+/// Print a table of the transaction targets with their old and new versions
+pub fn print_summary(handle: &Alpm) {
+    let mut rows = Vec::new();
+    let mut download_size = 0;
+    let mut installed_size = 0;
+    let mut net_size = 0;
+
+    for package in handle.trans_add() {
+        let old = handle.localdb().pkg(package.name()).ok();
+        let old_size = old.as_ref().map_or(0, |old| old.isize());
+        download_size += package.download_size();
+        installed_size += package.isize();
+        net_size += package.isize() - old_size;
+        rows.push([
+            format!(
+                "{}/{}",
+                package.db().map_or("local", |db| db.name()),
+                package.name()
+            ),
+            old.map_or_else(String::new, |old| old.version().to_string()),
+            package.version().to_string(),
+            format_size(package.isize() - old_size),
+        ]);
+    }
+    for package in handle.trans_remove() {
+        net_size -= package.isize();
+        rows.push([
+            format!("local/{}", package.name()),
+            package.version().to_string(),
+            String::new(),
+            format_size(-package.isize()),
+        ]);
+    }
+
+    let header = [
+        format!("Package ({})", rows.len()),
+        "Old Version".to_string(),
+        "New Version".to_string(),
+        "Net Change".to_string(),
+    ];
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3]
+        );
+    }
+    println!();
+    if handle.trans_add().is_empty() {
+        println!("Total Removed Size: {}", format_size(-net_size));
+    } else {
+        println!("Total Download Size: {}", format_size(download_size));
+        println!("Total Installed Size: {}", format_size(installed_size));
+        println!("Net Upgrade Size: {}", format_size(net_size));
+    }
+    println!();
+}
+
+/// A transaction target as shown by `--print`
+pub struct PrintTarget<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub repo: &'a str,
+    pub size: i64,
+    pub location: String,
+    pub base: &'a str,
+    pub arch: &'a str,
+}
+
+impl PrintTarget<'_> {
+    /// Expand pacman's `%n %v %r %s %l %b %a` placeholders
+    pub fn format(&self, format: &str) -> String {
+        let mut result = String::with_capacity(format.len());
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => result.push_str(self.name),
+                Some('v') => result.push_str(self.version),
+                Some('r') => result.push_str(self.repo),
+                Some('s') => result.push_str(&self.size.to_string()),
+                Some('l') => result.push_str(&self.location),
+                Some('b') => result.push_str(self.base),
+                Some('a') => result.push_str(self.arch),
+                Some(other) => {
+                    result.push('%');
+                    result.push(other);
+                }
+                None => result.push('%'),
+            }
+        }
+        result
+    }
+}
+
+/// Print the targets of a prepared transaction instead of committing it
+pub fn print_targets(handle: &Alpm, format: Option<&str>) {
+    let format = format.unwrap_or("%l");
+    for package in handle.trans_add().chain(handle.trans_remove()) {
+        let target = PrintTarget {
+            name: package.name(),
+            version: package.version(),
+            repo: package.db().map_or("", |db| db.name()),
+            size: package.size(),
+            location: package_location(handle, &package),
+            base: package.base().unwrap_or(""),
+            arch: package.arch().unwrap_or(""),
+        };
+        println!("{}", target.format(format));
+    }
+}
+
+/// Where a package comes from: a mirror URL or cached file, the loaded file or `name-version`
+fn package_location(handle: &Alpm, package: &Package) -> String {
+    match package.origin() {
+        PackageFrom::SyncDb => {
+            let cached = handle
+                .cachedirs()
+                .map(|dir| PathBuf::from(dir).join(package.filename()))
+                .find(|path| path.exists());
+            match cached {
+                Some(path) => format!("file://{}", path.display()),
+                None => package
+                    .db()
+                    .and_then(|db| db.servers().next().map(String::from))
+                    .map_or_else(String::new, |server| {
+                        format!("{}/{}", server, package.filename())
+                    }),
+            }
+        }
+        PackageFrom::File => package.filename().to_string(),
+        PackageFrom::LocalDb => format!("{}-{}", package.name(), package.version()),
+    }
+}
+
+pub fn confirm(prompt: &str, no_confirm: bool) -> bool {
+    no_confirm
+        || Confirm::new()
+            .with_prompt(prompt)
+            .default(true)
+            .interact()
+            .unwrap_or(false)
+}
+
+/// Ask a question that defaults to no, `no_confirm` declines it like pacman's `noyes`
+pub fn noyes(prompt: &str, no_confirm: bool) -> bool {
+    !no_confirm
+        && Confirm::new()
+            .with_prompt(prompt)
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+}
+
+/// Whether `name` matches a pattern with `*` and `?` wildcards, like pacman's fnmatch
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => name.is_empty(),
+        Some('*') => name
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain(Some(name.len()))
+            .any(|index| glob_match(chars.as_str(), &name[index..])),
+        Some(wanted) => {
+            let mut rest = name.chars();
+            match rest.next() {
+                Some(found) if wanted == '?' || wanted == found => {
+                    glob_match(chars.as_str(), rest.as_str())
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Run `f` inside a transaction that is released whatever the outcome
+pub fn transaction<T>(
+    handle: &mut Alpm,
+    flags: TransFlag,
+    f: impl FnOnce(&mut Alpm) -> Result<T>,
+) -> Result<T> {
+    handle.trans_init(flags)?;
+    let result = f(handle);
+    let released = handle.trans_release();
+    let value = result?;
+    released?;
+    Ok(value)
+}
+
+pub fn trans_prepare(handle: &mut Alpm) -> Result<()> {
+    handle.trans_prepare().map_err(|(ret, err)| {
+        print_prepare_error(ret);
+        Error::Failed(format!("failed to prepare transaction ({})", err))
+    })
+}
+
+pub fn trans_commit(handle: &mut Alpm) -> Result<()> {
+    handle.trans_commit().map_err(|(ret, err)| {
+        if interrupt::interrupted() {
+            return Error::Interrupted;
+        }
+        print_commit_error(ret);
+        Error::Failed(format!("failed to commit transaction ({})", err))
+    })
+}
+
+pub fn print_prepare_error(err: PrepareReturn) {
+    match err {
+        PrepareReturn::PkgInvalidArch(packages) => {
+            for package in packages {
+                eprintln!(
+                    "package {}-{} does not have a valid architecture",
+                    package.name(),
+                    package.version()
+                );
+            }
+        }
+        PrepareReturn::UnsatisfiedDeps(deps) => {
+            for dep in deps {
+                match dep.causing_pkg() {
+                    Some(causing) => eprintln!(
+                        "removing {} breaks dependency '{}' required by {}",
+                        causing,
+                        dep.depend(),
+                        dep.target()
+                    ),
+                    None => eprintln!(
+                        "unable to satisfy dependency '{}' required by {}",
+                        dep.depend(),
+                        dep.target()
+                    ),
+                }
+            }
+        }
+        PrepareReturn::ConflictingDeps(conflicts) => {
+            for conflict in conflicts {
+                eprintln!(
+                    "{} and {} are in conflict",
+                    conflict.package1(),
+                    conflict.package2()
+                );
+            }
+        }
+        PrepareReturn::None => {}
+    }
+}
+
+pub fn print_commit_error(err: CommitReturn) {
+    match err {
+        CommitReturn::FileConflict(conflicts) => {
+            for conflict in conflicts {
+                match conflict.conflict_type() {
+                    FileConflictType::Target => eprintln!(
+                        "{} exists in both '{}' and '{}'",
+                        conflict.file(),
+                        conflict.target(),
+                        conflict.conflicting_target().unwrap_or("")
+                    ),
+                    FileConflictType::Filesystem => eprintln!(
+                        "{}: {} exists in filesystem",
+                        conflict.target(),
+                        conflict.file()
+                    ),
+                }
+            }
+        }
+        CommitReturn::PkgInvalid(files) => {
+            for file in files {
+                eprintln!("{} is invalid or corrupted", file);
+            }
+        }
+        CommitReturn::None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn entry(path: &str, kind: mtree::EntryType) -> mtree::Entry {
+        mtree::Entry {
+            path: path.to_string(),
+            kind,
+            mode: None,
+            uid: None,
+            gid: None,
+            size: None,
+            time: None,
+            link: None,
+            sha256: None,
+        }
+    }
+
+    /// Installed packages by name with their reason, depends and optdepends
+    struct FakeLocal {
+        packages: Vec<(&'static str, bool, Vec<&'static str>, Vec<&'static str>)>,
+    }
+
+    impl LocalPackages for FakeLocal {
+        fn installed(&self) -> Result<Vec<String>> {
+            Ok(self
+                .packages
+                .iter()
+                .map(|package| package.0.to_string())
+                .collect())
+        }
+
+        fn explicit(&self) -> Result<Vec<String>> {
+            Ok(self
+                .packages
+                .iter()
+                .filter(|package| package.1)
+                .map(|package| package.0.to_string())
+                .collect())
+        }
+
+        fn dependencies(&self, name: &str, optional: bool) -> Result<Vec<String>> {
+            let package = self
+                .packages
+                .iter()
+                .find(|package| package.0 == name)
+                .unwrap();
+            let optdepends = package.3.iter().filter(|_| optional);
+            Ok(package
+                .2
+                .iter()
+                .chain(optdepends)
+                .map(|dep| dep.to_string())
+                .collect())
+        }
+
+        fn satisfier(&self, dep: &str) -> Result<Option<String>> {
+            Ok(self
+                .packages
+                .iter()
+                .find(|package| package.0 == dep)
+                .map(|package| package.0.to_string()))
+        }
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn orphans_include_unused_cycles() {
+        let local = FakeLocal {
+            packages: vec![
+                ("app", true, vec!["lib"], vec![]),
+                ("lib", false, vec![], vec![]),
+                ("a", false, vec!["b"], vec![]),
+                ("b", false, vec!["a"], vec![]),
+                ("leaf", false, vec![], vec![]),
+            ],
+        };
+        assert_eq!(orphans(&local, true).unwrap(), names(&["a", "b", "leaf"]));
+    }
+
+    #[test]
+    fn orphans_keep_optional_dependencies_once() {
+        let local = FakeLocal {
+            packages: vec![
+                ("app", true, vec!["lib"], vec!["plugin"]),
+                ("lib", false, vec![], vec![]),
+                ("plugin", false, vec!["helper"], vec![]),
+                ("helper", false, vec![], vec![]),
+            ],
+        };
+        // -Qdt
+        assert_eq!(orphans(&local, true).unwrap(), names(&[]));
+        // -Qdtt
+        assert_eq!(
+            orphans(&local, false).unwrap(),
+            names(&["plugin", "helper"])
+        );
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("glibc", "glibc"));
+        assert!(!glob_match("glibc", "glibc-locales"));
+        assert!(glob_match("linux*", "linux"));
+        assert!(glob_match("linux*", "linux-lts"));
+        assert!(glob_match("*-git", "rpac-git"));
+        assert!(glob_match("lib?2", "libx2"));
+        assert!(!glob_match("lib?2", "lib2"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn check_entry_mismatches() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        let tool = root.path().join("usr/bin/tool");
+        fs::write(&tool, "hello").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("tool.1", root.path().join("usr/bin/link")).unwrap();
+        let metadata = fs::metadata(&tool).unwrap();
+
+        let mut file = entry("usr/bin/tool", mtree::EntryType::File);
+        file.mode = Some(0o755);
+        file.uid = Some(metadata.uid());
+        file.gid = Some(metadata.gid());
+        file.size = Some(5);
+        file.time = Some(metadata.mtime());
+        file.sha256 = Some(format!("{:x}", Sha256::digest(b"hello")));
+        assert!(check_entry(root.path(), &file, false).is_empty());
+
+        file.mode = Some(0o644);
+        file.uid = Some(metadata.uid() + 1);
+        file.size = Some(6);
+        file.time = Some(metadata.mtime() - 1);
+        file.sha256 = Some(format!("{:x}", Sha256::digest(b"hello!")));
+        assert_eq!(
+            check_entry(root.path(), &file, false),
+            [
+                "Permissions",
+                "UID",
+                "Modification time",
+                "Size",
+                "SHA256 checksum"
+            ]
+        );
+        // Only the metadata of backup files is checked
+        assert_eq!(
+            check_entry(root.path(), &file, true),
+            ["Permissions", "UID"]
+        );
+
+        let mut link = entry("usr/bin/link", mtree::EntryType::Link);
+        link.link = Some("tool.2".to_string());
+        assert_eq!(check_entry(root.path(), &link, false), ["Symlink path"]);
+        link.link = Some("tool.1".to_string());
+        assert!(check_entry(root.path(), &link, false).is_empty());
+
+        let dir = entry("usr/bin/tool", mtree::EntryType::Dir);
+        assert_eq!(check_entry(root.path(), &dir, false), ["File type"]);
+        let missing = entry("usr/bin/missing", mtree::EntryType::File);
+        assert_eq!(check_entry(root.path(), &missing, false), ["Existence"]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Interrupting rpac while it holds the database lock of a temporary root
+
+use std::{
+    fs,
+    net::TcpListener,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Accept connections without ever answering, so a refresh hangs until interrupted
+fn stalling_mirror() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let mut connections = Vec::new();
+        for stream in listener.incoming() {
+            connections.push(stream);
+        }
+    });
+    url
+}
+
+fn wait_for(path: &Path) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if path.exists() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn interrupted_refresh_unlocks_and_exits_with_130() {
+    let root = tempfile::tempdir().unwrap();
+    let dbpath = root.path().join("var/lib/pacman");
+    fs::create_dir_all(&dbpath).unwrap();
+    let config = root.path().join("pacman.conf");
+    fs::write(
+        &config,
+        format!(
+            "[options]\n\
+             RootDir = {root}\n\
+             DBPath = {dbpath}/\n\
+             CacheDir = {root}/cache/\n\
+             GPGDir = {root}/gnupg/\n\
+             LogFile = {root}/pacman.log\n\
+             SigLevel = Never\n\
+             \n\
+             [stalled]\n\
+             Server = {server}\n",
+            root = root.path().display(),
+            dbpath = dbpath.display(),
+            server = stalling_mirror(),
+        ),
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rpac"))
+        .arg("--config")
+        .arg(&config)
+        .arg("-Sy")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let lock = dbpath.join("db.lck");
+    if !wait_for(&lock) {
+        child.kill().unwrap();
+        panic!("rpac never locked {}", dbpath.display());
+    }
+    let killed = Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Interrupt signal received"));
+    assert!(!lock.exists());
+}